@@ -7,6 +7,74 @@ MEMORY
 /* The entry point is the reset handler */
 ENTRY(Reset);
 
+/* Interrupt and exception handlers not defined by the application */
+PROVIDE(DefaultHandler = DefaultHandler_);
+PROVIDE(ExceptionHandler = DefaultExceptionHandler_);
+PROVIDE(eclic_msip_handler = DefaultHandler);
+PROVIDE(eclic_mtip_handler = DefaultHandler);
+PROVIDE(eclic_bwei_handler = DefaultHandler);
+PROVIDE(eclic_pmovi_handler = DefaultHandler);
+PROVIDE(WWDGT_IRQHandler = DefaultHandler);
+PROVIDE(LVD_IRQHandler = DefaultHandler);
+PROVIDE(TAMPER_IRQHandler = DefaultHandler);
+PROVIDE(RTC_IRQHandler = DefaultHandler);
+PROVIDE(FMC_IRQHandler = DefaultHandler);
+PROVIDE(RCU_IRQHandler = DefaultHandler);
+PROVIDE(EXTI0_IRQHandler = DefaultHandler);
+PROVIDE(EXTI1_IRQHandler = DefaultHandler);
+PROVIDE(EXTI2_IRQHandler = DefaultHandler);
+PROVIDE(EXTI3_IRQHandler = DefaultHandler);
+PROVIDE(EXTI4_IRQHandler = DefaultHandler);
+PROVIDE(DMA0_Channel0_IRQHandler = DefaultHandler);
+PROVIDE(DMA0_Channel1_IRQHandler = DefaultHandler);
+PROVIDE(DMA0_Channel2_IRQHandler = DefaultHandler);
+PROVIDE(DMA0_Channel3_IRQHandler = DefaultHandler);
+PROVIDE(DMA0_Channel4_IRQHandler = DefaultHandler);
+PROVIDE(DMA0_Channel5_IRQHandler = DefaultHandler);
+PROVIDE(DMA0_Channel6_IRQHandler = DefaultHandler);
+PROVIDE(ADC0_1_IRQHandler = DefaultHandler);
+PROVIDE(CAN0_TX_IRQHandler = DefaultHandler);
+PROVIDE(CAN0_RX0_IRQHandler = DefaultHandler);
+PROVIDE(CAN0_RX1_IRQHandler = DefaultHandler);
+PROVIDE(CAN0_EWMC_IRQHandler = DefaultHandler);
+PROVIDE(EXTI5_9_IRQHandler = DefaultHandler);
+PROVIDE(TIMER0_BRK_IRQHandler = DefaultHandler);
+PROVIDE(TIMER0_UP_IRQHandler = DefaultHandler);
+PROVIDE(TIMER0_TRG_CMT_IRQHandler = DefaultHandler);
+PROVIDE(TIMER0_Channel_IRQHandler = DefaultHandler);
+PROVIDE(TIMER1_IRQHandler = DefaultHandler);
+PROVIDE(TIMER2_IRQHandler = DefaultHandler);
+PROVIDE(TIMER3_IRQHandler = DefaultHandler);
+PROVIDE(I2C0_EV_IRQHandler = DefaultHandler);
+PROVIDE(I2C0_ER_IRQHandler = DefaultHandler);
+PROVIDE(I2C1_EV_IRQHandler = DefaultHandler);
+PROVIDE(I2C1_ER_IRQHandler = DefaultHandler);
+PROVIDE(SPI0_IRQHandler = DefaultHandler);
+PROVIDE(SPI1_IRQHandler = DefaultHandler);
+PROVIDE(USART0_IRQHandler = DefaultHandler);
+PROVIDE(USART1_IRQHandler = DefaultHandler);
+PROVIDE(USART2_IRQHandler = DefaultHandler);
+PROVIDE(EXTI10_15_IRQHandler = DefaultHandler);
+PROVIDE(RTC_Alarm_IRQHandler = DefaultHandler);
+PROVIDE(USBFS_WKUP_IRQHandler = DefaultHandler);
+PROVIDE(EXMC_IRQHandler = DefaultHandler);
+PROVIDE(TIMER4_IRQHandler = DefaultHandler);
+PROVIDE(SPI2_IRQHandler = DefaultHandler);
+PROVIDE(UART3_IRQHandler = DefaultHandler);
+PROVIDE(UART4_IRQHandler = DefaultHandler);
+PROVIDE(TIMER5_IRQHandler = DefaultHandler);
+PROVIDE(TIMER6_IRQHandler = DefaultHandler);
+PROVIDE(DMA1_Channel0_IRQHandler = DefaultHandler);
+PROVIDE(DMA1_Channel1_IRQHandler = DefaultHandler);
+PROVIDE(DMA1_Channel2_IRQHandler = DefaultHandler);
+PROVIDE(DMA1_Channel3_IRQHandler = DefaultHandler);
+PROVIDE(DMA1_Channel4_IRQHandler = DefaultHandler);
+PROVIDE(CAN1_TX_IRQHandler = DefaultHandler);
+PROVIDE(CAN1_RX0_IRQHandler = DefaultHandler);
+PROVIDE(CAN1_RX1_IRQHandler = DefaultHandler);
+PROVIDE(CAN1_EWMC_IRQHandler = DefaultHandler);
+PROVIDE(USBFS_IRQHandler = DefaultHandler);
+
 SECTIONS {
     __stacktop = ORIGIN(RAM) + LENGTH(RAM);
 
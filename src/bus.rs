@@ -0,0 +1,228 @@
+/* Register access backends */
+// Every driver goes through `register_helpers`, which dispatches to the `Bus`
+// selected for the current target: plain volatile accesses on the chip, and an
+// in-memory simulation on the host so the drivers can be unit-tested.
+
+// Register addresses. Only `reg32`/`reg8` in `register_helpers` create them,
+// from the register maps in this crate.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Reg32(pub(crate) u32);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Reg8(pub(crate) u32);
+
+pub trait RegisterBus {
+    /// # Safety
+    ///
+    /// `register` must be a memory-mapped register that can be read as a
+    /// whole word without side effects the caller isn't prepared for.
+    unsafe fn read(register: Reg32) -> u32;
+    /// # Safety
+    ///
+    /// `register` must be a memory-mapped register that may be written as a
+    /// whole word.
+    unsafe fn write(register: Reg32, value: u32);
+    // Byte-wide access, for registers like the ECLIC's that must not be
+    // written as whole words.
+    /// # Safety
+    ///
+    /// As `read`, for a byte-wide register.
+    unsafe fn read8(register: Reg8) -> u8;
+    /// # Safety
+    ///
+    /// As `write`, for a byte-wide register.
+    unsafe fn write8(register: Reg8, value: u8);
+}
+
+pub struct VolatileBus;
+
+impl RegisterBus for VolatileBus {
+    unsafe fn read(register: Reg32) -> u32 {
+        unsafe { core::ptr::read_volatile(register.0 as *const u32) }
+    }
+
+    unsafe fn write(register: Reg32, value: u32) {
+        unsafe {
+            core::ptr::write_volatile(register.0 as *mut u32, value);
+        }
+    }
+
+    unsafe fn read8(register: Reg8) -> u8 {
+        unsafe { core::ptr::read_volatile(register.0 as *const u8) }
+    }
+
+    unsafe fn write8(register: Reg8, value: u8) {
+        unsafe {
+            core::ptr::write_volatile(register.0 as *mut u8, value);
+        }
+    }
+}
+
+#[cfg(target_arch = "riscv32")]
+pub type Bus = VolatileBus;
+
+#[cfg(not(target_arch = "riscv32"))]
+pub type Bus = sim::SimBus;
+
+#[cfg(not(target_arch = "riscv32"))]
+pub mod sim {
+    use super::{Reg32, Reg8, RegisterBus};
+    use std::cell::RefCell;
+    use std::collections::BTreeMap;
+    use std::vec::Vec;
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+    pub enum Access {
+        Read(u32, u32),  // (address, value returned)
+        Write(u32, u32), // (address, value written)
+        Read8(u32, u8),
+        Write8(u32, u8),
+    }
+
+    #[derive(Default)]
+    struct State {
+        registers: BTreeMap<u32, u32>,
+        log: Vec<Access>,
+    }
+
+    // One bus per thread, so tests running in parallel don't see each other.
+    thread_local! {
+        static STATE: RefCell<State> = RefCell::new(State::default());
+    }
+
+    // Registers behave like plain memory: a read returns the last value written
+    // (or preset), and every register is zero until then. Byte accesses go to
+    // the matching byte of the (little-endian) word.
+    pub struct SimBus;
+
+    impl RegisterBus for SimBus {
+        unsafe fn read(register: Reg32) -> u32 {
+            let address = register.0;
+            STATE.with(|state| {
+                let mut state = state.borrow_mut();
+                let value = state.registers.get(&address).copied().unwrap_or(0);
+                state.log.push(Access::Read(address, value));
+                value
+            })
+        }
+
+        unsafe fn write(register: Reg32, value: u32) {
+            let address = register.0;
+            STATE.with(|state| {
+                let mut state = state.borrow_mut();
+                state.registers.insert(address, value);
+                state.log.push(Access::Write(address, value));
+            })
+        }
+
+        unsafe fn read8(register: Reg8) -> u8 {
+            let address = register.0;
+            let (word, shift) = (address & !0x3, 8 * (address & 0x3));
+            STATE.with(|state| {
+                let mut state = state.borrow_mut();
+                let value = (state.registers.get(&word).copied().unwrap_or(0) >> shift) as u8;
+                state.log.push(Access::Read8(address, value));
+                value
+            })
+        }
+
+        unsafe fn write8(register: Reg8, value: u8) {
+            let address = register.0;
+            let (word, shift) = (address & !0x3, 8 * (address & 0x3));
+            STATE.with(|state| {
+                let mut state = state.borrow_mut();
+                let old = state.registers.get(&word).copied().unwrap_or(0);
+                let new = (old & !(0xff << shift)) | ((value as u32) << shift);
+                state.registers.insert(word, new);
+                state.log.push(Access::Write8(address, value));
+            })
+        }
+    }
+
+    // Clear all registers and the access log.
+    pub fn reset() {
+        STATE.with(|state| *state.borrow_mut() = State::default());
+    }
+
+    // Set a register without recording an access, e.g. to fake a status flag.
+    pub fn preset(address: u32, value: u32) {
+        STATE.with(|state| {
+            state.borrow_mut().registers.insert(address, value);
+        });
+    }
+
+    // Read a register without recording an access.
+    pub fn peek(address: u32) -> u32 {
+        STATE.with(|state| state.borrow().registers.get(&address).copied().unwrap_or(0))
+    }
+
+    // Return every access since the last call (or `reset`) and clear the log.
+    pub fn take_log() -> Vec<Access> {
+        STATE.with(|state| core::mem::take(&mut state.borrow_mut().log))
+    }
+
+    // Like `take_log`, but only the writes, as (address, value) pairs.
+    pub fn take_writes() -> Vec<(u32, u32)> {
+        take_log()
+            .into_iter()
+            .filter_map(|access| match access {
+                Access::Write(address, value) => Some((address, value)),
+                Access::Write8(address, value) => Some((address, value as u32)),
+                Access::Read(..) | Access::Read8(..) => None,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sim::{self, Access};
+    use crate::register_helpers::*;
+
+    const REG: u32 = 0x4000_0000;
+
+    #[test]
+    fn set_and_reset_bits_read_modify_write() {
+        sim::reset();
+        sim::preset(REG, 0x0f);
+
+        set_bits(reg32(REG), bit(8));
+        reset_bits(reg32(REG), bit(0));
+
+        assert_eq!(
+            sim::take_log(),
+            [
+                Access::Read(REG, 0x0f),
+                Access::Write(REG, 0x10f),
+                Access::Read(REG, 0x10f),
+                Access::Write(REG, 0x10e),
+            ]
+        );
+        assert_eq!(sim::peek(REG), 0x10e);
+    }
+
+    #[test]
+    fn preset_and_peek_are_not_logged() {
+        sim::reset();
+        sim::preset(REG, 42);
+
+        assert_eq!(sim::peek(REG), 42);
+        assert_eq!(read_register(reg32(REG)), 42);
+        assert_eq!(sim::take_log(), [Access::Read(REG, 42)]);
+    }
+
+    #[test]
+    fn byte_access_hits_its_lane() {
+        sim::reset();
+        sim::preset(REG, 0x4433_2211);
+
+        assert_eq!(read_register8(reg8(REG + 2)), 0x33);
+        set_register8(reg8(REG + 1), 0xaa);
+
+        assert_eq!(sim::peek(REG), 0x4433_aa11);
+        assert_eq!(
+            sim::take_log(),
+            [Access::Read8(REG + 2, 0x33), Access::Write8(REG + 1, 0xaa)]
+        );
+    }
+}
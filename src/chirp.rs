@@ -0,0 +1,367 @@
+use crate::frequency::{PlanError, MIN_TICKS_PER_SAMPLE};
+use crate::timer::*;
+
+/* Frequency sweeps */
+// DMA keeps playing the same table while the sweep changes the timer
+// autoreload once per table cycle, so every cycle of the waveform is played at
+// a single rate. The prescaler stays fixed for the whole sweep; it is the
+// smallest one that still reaches the low end, which keeps the autoreload (and
+// so the frequency resolution) as large as possible at the high end.
+// Frequencies are in millihertz, like in `frequency`.
+
+const MAX_DIVIDER: u64 = 0x1_0000;
+
+// 2^(2^-i) for i = 1..=16 in Q30, for exp2.
+const EXP2_FRAC: [u64; 16] = [
+    1_518_500_250,
+    1_276_901_417,
+    1_170_923_762,
+    1_121_280_436,
+    1_097_253_708,
+    1_085_434_106,
+    1_079_572_136,
+    1_076_653_033,
+    1_075_196_443,
+    1_074_468_888,
+    1_074_105_294,
+    1_073_923_544,
+    1_073_832_680,
+    1_073_787_251,
+    1_073_764_537,
+    1_073_753_181,
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SweepShape {
+    // Equal frequency steps per unit of time.
+    Linear,
+    // Equal frequency ratios per unit of time, e.g. the same time per octave.
+    Logarithmic,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ChirpParameters {
+    // The stop frequency may be below the start frequency for a downward sweep.
+    pub start_millihertz: u64,
+    pub stop_millihertz: u64,
+    pub duration_ms: u32,
+    pub shape: SweepShape,
+    // Length of the table DMA plays.
+    pub table_len: usize,
+    // Start over when the sweep is done, instead of holding the stop frequency.
+    pub repeat: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChirpError {
+    // One end of the sweep can't be played, see `plan_output_frequency`.
+    Plan(PlanError),
+    ZeroDuration,
+}
+
+pub struct Chirp<T: TimerPeriph> {
+    timer: T,
+    // Timer clock scaled by 1000, to go with millihertz.
+    clock: u64,
+    psc_divider: u64,
+    table_len: u64,
+    parameters: ChirpParameters,
+    // log2(stop / start) in Q16.
+    log2_ratio: i64,
+    // Timer clock ticks.
+    duration: u64,
+    elapsed: u64,
+    autoreload: u16,
+    finished: bool,
+}
+
+impl<T: TimerPeriph> Chirp<T> {
+    pub fn new(
+        timer: T,
+        timer_clock_hz: u32,
+        parameters: &ChirpParameters,
+    ) -> Result<Chirp<T>, ChirpError> {
+        let (start, stop) = (parameters.start_millihertz, parameters.stop_millihertz);
+        if start == 0 || stop == 0 {
+            return Err(ChirpError::Plan(PlanError::ZeroFrequency));
+        }
+        if parameters.table_len == 0 {
+            return Err(ChirpError::Plan(PlanError::EmptyTable));
+        }
+        if parameters.duration_ms == 0 {
+            return Err(ChirpError::ZeroDuration);
+        }
+
+        let clock = timer_clock_hz as u64 * 1000;
+        let table_len = parameters.table_len as u64;
+        let (low, high) = (start.min(stop), start.max(stop));
+
+        if clock / MIN_TICKS_PER_SAMPLE as u64 / table_len < high {
+            return Err(ChirpError::Plan(PlanError::TooHigh));
+        }
+        let low_rate = low * table_len;
+        let psc_divider = clock.div_ceil(low_rate * MAX_DIVIDER).max(1);
+        if psc_divider > MAX_DIVIDER {
+            return Err(ChirpError::Plan(PlanError::TooLow));
+        }
+
+        let mut chirp = Chirp {
+            timer,
+            clock,
+            psc_divider,
+            table_len,
+            parameters: *parameters,
+            log2_ratio: log2_q16(stop) - log2_q16(start),
+            duration: parameters.duration_ms as u64 * timer_clock_hz as u64 / 1000,
+            elapsed: 0,
+            autoreload: 0,
+            finished: false,
+        };
+        chirp.autoreload = chirp.autoreload_for(start);
+        Ok(chirp)
+    }
+
+    // Programs the start frequency with autoreload shadowing, so later steps
+    // take effect at a sample boundary, and starts the timer.
+    pub fn start(&mut self) {
+        self.elapsed = 0;
+        self.finished = false;
+        self.autoreload = self.autoreload_for(self.parameters.start_millihertz);
+
+        let prescaler = (self.psc_divider - 1) as u16;
+        timer_prescaler_config(T::PERIPH, prescaler, TIMER_PSC_RELOAD_UPDATE);
+        timer_autoreload_value_config(T::PERIPH, self.autoreload);
+        timer_auto_reload_shadow_enable(T::PERIPH);
+        timer_enable(T::PERIPH);
+    }
+
+    // Stops the counter and gives back the timer.
+    pub fn stop(self) -> T {
+        timer_disable(T::PERIPH);
+        self.timer
+    }
+
+    // Call once per table cycle, e.g. on the DMA full transfer interrupt.
+    // Returns the frequency the next cycle is played at.
+    pub fn step(&mut self) -> u64 {
+        if self.finished {
+            return self.parameters.stop_millihertz;
+        }
+
+        let cycle = (self.autoreload as u64 + 1) * self.psc_divider * self.table_len;
+        self.elapsed += cycle;
+        if self.elapsed >= self.duration {
+            if self.parameters.repeat {
+                self.elapsed = 0;
+            } else {
+                self.finished = true;
+            }
+        }
+
+        let millihertz = if self.finished {
+            self.parameters.stop_millihertz
+        } else {
+            self.frequency_at(self.elapsed)
+        };
+        self.autoreload = self.autoreload_for(millihertz);
+        timer_autoreload_value_config(T::PERIPH, self.autoreload);
+        millihertz
+    }
+
+    // True once a sweep without repeat has reached the stop frequency.
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    pub fn prescaler(&self) -> u16 {
+        (self.psc_divider - 1) as u16
+    }
+
+    pub fn autoreload(&self) -> u16 {
+        self.autoreload
+    }
+
+    fn frequency_at(&self, elapsed: u64) -> u64 {
+        // Progress through the sweep in Q24; both sides are shifted down far
+        // enough for the product to fit.
+        let shift = (64 - self.duration.leading_zeros()).saturating_sub(39);
+        let duration = (self.duration >> shift).max(1);
+        let progress = (((elapsed >> shift) << 24) / duration) as i64;
+
+        let start = self.parameters.start_millihertz;
+        match self.parameters.shape {
+            SweepShape::Linear => {
+                let span = self.parameters.stop_millihertz as i64 - start as i64;
+                (start as i64 + ((span * progress) >> 24)) as u64
+            }
+            SweepShape::Logarithmic => exp2_scale(start, (self.log2_ratio * progress) >> 24),
+        }
+    }
+
+    fn autoreload_for(&self, millihertz: u64) -> u16 {
+        let step = self.psc_divider * millihertz * self.table_len;
+        let car_divider = ((self.clock + step / 2) / step).clamp(1, MAX_DIVIDER);
+        (car_divider - 1) as u16
+    }
+}
+
+// log2(x) in Q16, for x > 0.
+fn log2_q16(x: u64) -> i64 {
+    let n = 63 - x.leading_zeros();
+    // Mantissa in Q30, in [1, 2).
+    let mut y = if n >= 30 {
+        x >> (n - 30)
+    } else {
+        x << (30 - n)
+    };
+    let mut result = (n as i64) << 16;
+    for bit in (0..16).rev() {
+        y = (y * y) >> 30;
+        if y >= 2 << 30 {
+            y >>= 1;
+            result |= 1 << bit;
+        }
+    }
+    result
+}
+
+// value * 2^(exponent / 65536).
+fn exp2_scale(value: u64, exponent: i64) -> u64 {
+    let integer = exponent >> 16;
+    let fraction = exponent & 0xffff;
+    let mut multiplier: u64 = 1 << 30;
+    for (i, factor) in EXP2_FRAC.iter().enumerate() {
+        if fraction & (1 << (15 - i)) != 0 {
+            multiplier = (multiplier * factor) >> 30;
+        }
+    }
+    let scaled = (value * multiplier) >> 30;
+    if integer >= 0 {
+        scaled << integer
+    } else {
+        scaled >> -integer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::sim;
+
+    const TIMER5_CTL0: u32 = 0x4000_1000;
+    const TIMER5_PSC: u32 = 0x4000_1028;
+    const TIMER5_CAR: u32 = 0x4000_102c;
+
+    // 100 Hz to 1 kHz in 100 ms with 32 samples from 8 MHz: the first cycle
+    // takes 10 ms.
+    const SWEEP: ChirpParameters = ChirpParameters {
+        start_millihertz: 100_000,
+        stop_millihertz: 1_000_000,
+        duration_ms: 100,
+        shape: SweepShape::Linear,
+        table_len: 32,
+        repeat: false,
+    };
+
+    #[test]
+    fn fixed_point_log_and_exp() {
+        assert_eq!(log2_q16(1), 0);
+        assert_eq!(log2_q16(1024), 10 << 16);
+        // log2(3) = 1.5849625
+        assert_eq!(log2_q16(3), 103_872);
+        assert_eq!(exp2_scale(1_000_000, 1 << 15), 1_414_213);
+        assert_eq!(exp2_scale(1_000_000, -(3 << 16)), 125_000);
+    }
+
+    #[test]
+    fn linear_sweep_steps_autoreload() {
+        sim::reset();
+        let mut chirp = Chirp::new(unsafe { Timer5::steal() }, 8_000_000, &SWEEP).unwrap();
+        chirp.start();
+
+        assert_eq!(sim::peek(TIMER5_PSC), 0);
+        assert_eq!(sim::peek(TIMER5_CAR), 2499);
+        // ARSE, CEN
+        assert_eq!(sim::peek(TIMER5_CTL0), 0x81);
+
+        // 10% through: 190 Hz, 8 MHz / (190 Hz * 32) = 1315.8 ticks
+        assert_eq!(chirp.step(), 189_999);
+        assert_eq!(sim::peek(TIMER5_CAR), 1315);
+
+        while !chirp.is_finished() {
+            chirp.step();
+        }
+        assert_eq!(sim::peek(TIMER5_CAR), 249);
+        assert_eq!(chirp.step(), 1_000_000);
+
+        chirp.stop();
+        assert_eq!(sim::peek(TIMER5_CTL0), 0x80);
+    }
+
+    #[test]
+    fn logarithmic_sweep_is_geometric() {
+        let parameters = ChirpParameters {
+            shape: SweepShape::Logarithmic,
+            ..SWEEP
+        };
+        let chirp = Chirp::new(unsafe { Timer5::steal() }, 8_000_000, &parameters).unwrap();
+
+        // Halfway through the sweep is the geometric mean, 316.23 Hz.
+        assert_eq!(chirp.frequency_at(chirp.duration / 2), 316_226);
+        assert_eq!(chirp.frequency_at(0), 100_000);
+    }
+
+    #[test]
+    fn repeat_and_downward_sweeps() {
+        sim::reset();
+        let parameters = ChirpParameters {
+            start_millihertz: 1_000_000,
+            stop_millihertz: 100_000,
+            repeat: true,
+            ..SWEEP
+        };
+        let mut chirp = Chirp::new(unsafe { Timer5::steal() }, 8_000_000, &parameters).unwrap();
+        chirp.start();
+        assert_eq!(chirp.autoreload(), 249);
+
+        let mut previous = chirp.step();
+        loop {
+            let millihertz = chirp.step();
+            if millihertz > previous {
+                // Wrapped around to the start.
+                assert_eq!(millihertz, 1_000_000);
+                break;
+            }
+            previous = millihertz;
+        }
+        assert!(!chirp.is_finished());
+    }
+
+    #[test]
+    fn prescaler_fits_low_end() {
+        // 108 MHz / (1 Hz * 32) = 3.375M ticks per sample, above 65536.
+        let parameters = ChirpParameters {
+            start_millihertz: 1_000,
+            ..SWEEP
+        };
+        let chirp = Chirp::new(unsafe { Timer5::steal() }, 108_000_000, &parameters).unwrap();
+        assert_eq!(chirp.prescaler(), 51);
+
+        let too_fast = ChirpParameters {
+            stop_millihertz: 300_000_000,
+            ..SWEEP
+        };
+        assert_eq!(
+            Chirp::new(unsafe { Timer5::steal() }, 8_000_000, &too_fast).err(),
+            Some(ChirpError::Plan(PlanError::TooHigh))
+        );
+        let no_time = ChirpParameters {
+            duration_ms: 0,
+            ..SWEEP
+        };
+        assert_eq!(
+            Chirp::new(unsafe { Timer5::steal() }, 8_000_000, &no_time).err(),
+            Some(ChirpError::ZeroDuration)
+        );
+    }
+}
@@ -0,0 +1,473 @@
+use crate::chirp::*;
+use crate::dac::*;
+use crate::dma::*;
+use crate::eclic::*;
+use crate::exti::*;
+use crate::frequency::*;
+use crate::gpio::*;
+use crate::rcu::*;
+use crate::timer::*;
+use crate::waveform::*;
+
+// The Longan Nano has an 8 MHz crystal.
+const HXTAL_HZ: u32 = 8_000_000;
+const SYSCLK_HZ: u32 = 108_000_000;
+
+const OUTPUT_MILLIHERTZ: u64 = 1_000_000;
+
+const SIZE: usize = 32;
+// A static, not a const: DMA reads it at a fixed address.
+static SINE: [u16; SIZE] = {
+    let mut table = [0; SIZE];
+    Waveform::new(Shape::Sine, Resolution::Bits12).fill(&mut table);
+    table
+};
+
+pub fn clock_config() -> Clocks {
+    ClockConfig::new()
+        .hxtal(HXTAL_HZ)
+        .sysclk(SYSCLK_HZ)
+        .freeze()
+        .unwrap()
+}
+
+pub fn rcu_config() {
+    rcu_periph_clock_enable(RcuPeriph::GpioA);
+    rcu_periph_clock_enable(RcuPeriph::GpioC);
+    rcu_periph_clock_enable(RcuPeriph::Dma1);
+    rcu_periph_clock_enable(RcuPeriph::Dac);
+    rcu_periph_clock_enable(RcuPeriph::Timer5);
+}
+
+// Steps `dac0` to its next sample on every `trigger` edge of `pin`, pin 9 of
+// any port, e.g. PB9. The line is set up in event mode, so the pulses don't
+// interrupt the CPU.
+pub fn dac_exti9_trigger_config<P: Exti9Pin>(dac0: &mut Dac0, _pin: P, trigger: ExtiTrigger) {
+    rcu_periph_clock_enable(RcuPeriph::Af);
+    // Every Exti9Pin is on a port AFIO can route.
+    gpio_exti_source_select(P::PERIPH, 9).unwrap();
+
+    exti_init(EXTI_9, ExtiMode::Event, trigger);
+    exti_flag_clear(EXTI_9);
+
+    dac0.trigger_config(DacTrigger::Exti9);
+}
+
+/* RGB LED */
+// The Longan Nano's LED has its red part on PC13, green on PA1 and blue on
+// PA2, each lit when the pin is low.
+pub struct Led {
+    red: PC13<Output<PushPull>>,
+    green: PA1<Output<PushPull>>,
+    blue: PA2<Output<PushPull>>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LedColor {
+    Off,
+    Red,
+    Green,
+    Blue,
+    Yellow,
+    Cyan,
+    Magenta,
+    White,
+}
+
+impl LedColor {
+    const fn components(self) -> (bool, bool, bool) {
+        match self {
+            LedColor::Off => (false, false, false),
+            LedColor::Red => (true, false, false),
+            LedColor::Green => (false, true, false),
+            LedColor::Blue => (false, false, true),
+            LedColor::Yellow => (true, true, false),
+            LedColor::Cyan => (false, true, true),
+            LedColor::Magenta => (true, false, true),
+            LedColor::White => (true, true, true),
+        }
+    }
+}
+
+// Takes the pins in any mode and turns the LED off. The outputs reset low, so
+// it may light up for a few cycles in between.
+pub fn led_config<R, G, B>(pc13: PC13<R>, pa1: PA1<G>, pa2: PA2<B>) -> Led {
+    let mut led = Led {
+        red: pc13.into_push_pull_output(GpioSpeed::Mhz2),
+        green: pa1.into_push_pull_output(GpioSpeed::Mhz2),
+        blue: pa2.into_push_pull_output(GpioSpeed::Mhz2),
+    };
+    led_set(&mut led, LedColor::Off);
+    led
+}
+
+pub fn led_set(led: &mut Led, color: LedColor) {
+    let (red, green, blue) = color.components();
+    if red {
+        led.red.set_low();
+    } else {
+        led.red.set_high();
+    }
+    if green {
+        led.green.set_low();
+    } else {
+        led.green.set_high();
+    }
+    if blue {
+        led.blue.set_low();
+    } else {
+        led.blue.set_high();
+    }
+}
+
+pub fn dac_config(
+    mut dac: Dac,
+    pa4: PA4<Analog>,
+    pa5: PA5<Analog>,
+    timer5: &mut Timer5,
+) -> (Dac0, Dac1) {
+    dac.deinit();
+    let (mut dac0, dac1) = dac.split(pa4, pa5);
+
+    dac0.trigger_config(DacTrigger::Timer5(timer5));
+    dac0.wave_mode_config(WaveMode::Disabled);
+    dac0.output_buffer_enable();
+
+    dac0.enable();
+    dac0.dma_enable();
+
+    (dac0, dac1)
+}
+
+// TRGO is already set up by `dac_config`.
+pub fn timer5_config(timer5: &mut Timer5, clocks: &Clocks) {
+    let plan = plan_output_frequency(clocks.timer_apb1(), OUTPUT_MILLIHERTZ, SIZE).unwrap();
+    // SINE has a fixed length, it cannot be regenerated.
+    assert_eq!(plan.table_len, SIZE);
+    plan.apply(timer5.periph());
+
+    timer_enable(timer5.periph());
+}
+
+// Alternative to `dma_config` that plays SINE from the TIMER5 update interrupt.
+// Call `on_interrupt` of the result from `TIMER5_IRQHandler` and write the
+// samples with `Dac0::data_set`; with DAC0's trigger disabled they show up at
+// once. Turns on interrupts globally (mstatus.MIE).
+pub fn sample_player_config(timer5: Timer5, clocks: &Clocks) -> SamplePlayer<Timer5> {
+    let plan = plan_output_frequency(clocks.timer_apb1(), OUTPUT_MILLIHERTZ, SIZE).unwrap();
+    assert_eq!(plan.table_len, SIZE);
+    plan.apply(timer5.periph());
+
+    eclic_irq_enable(Interrupt::Timer5, 0, 0).unwrap();
+    let player = SamplePlayer::start(timer5, &SINE).unwrap();
+    eclic_global_interrupt_enable();
+    player
+}
+
+// 20 Hz to 20 kHz in 10 s, over and over, for frequency response measurements
+// on PA4.
+const CHIRP: ChirpParameters = ChirpParameters {
+    start_millihertz: 20_000,
+    stop_millihertz: 20_000_000,
+    duration_ms: 10_000,
+    shape: SweepShape::Logarithmic,
+    table_len: SIZE,
+    repeat: true,
+};
+
+// Alternative to `timer5_config` that sweeps SINE through CHIRP. Call
+// `chirp_on_interrupt` from `DMA1_Channel2_IRQHandler`. Turns on interrupts
+// globally (mstatus.MIE).
+pub fn chirp_config(
+    timer5: Timer5,
+    channel: &mut <Dac0 as DmaRequest>::Channel,
+    clocks: &Clocks,
+) -> Chirp<Timer5> {
+    let mut chirp = Chirp::new(timer5, clocks.timer_apb1(), &CHIRP).unwrap();
+
+    channel.interrupt_flag_clear(DMA_INTF_FTFIF);
+    channel.interrupt_enable(DMA_INT_FTF);
+    eclic_irq_enable(Interrupt::Dma1Channel2, 0, 0).unwrap();
+
+    chirp.start();
+    eclic_global_interrupt_enable();
+    chirp
+}
+
+pub fn chirp_on_interrupt(chirp: &mut Chirp<Timer5>, channel: &mut <Dac0 as DmaRequest>::Channel) {
+    if channel.interrupt_flag_get(DMA_INTF_FTFIF) {
+        channel.interrupt_flag_clear(DMA_INTF_FTFIF);
+        chirp.step();
+    }
+}
+
+pub fn dma_config(channel: &mut <Dac0 as DmaRequest>::Channel) {
+    channel.flag_clear(DMA_INTF_GIF);
+    channel.flag_clear(DMA_INTF_FTFIF);
+    channel.flag_clear(DMA_INTF_HTFIF);
+    channel.flag_clear(DMA_INTF_ERRIF);
+
+    let pointer = SINE.as_ptr() as u32;
+    let a = DmaParameters {
+        periph_addr: Dac0::data_address(DacAlign::Right12),
+        periph_width: DmaWidth::Bits16,
+        memory_addr: pointer,
+        memory_width: DmaWidth::Bits16,
+        number: SIZE as u32,
+        priority: DmaPriority::UltraHigh,
+        periph_inc: DmaIncrement::Fixed,
+        memory_inc: DmaIncrement::Increase,
+        direction: DmaDirection::MemoryToPeripheral,
+    };
+
+    channel.init(&a).unwrap();
+    channel.circulation_enable();
+    channel.channel_enable();
+}
+
+// Plays `samples` (built with `dacc_data_pack`) on both channels from one DMA
+// stream; use together with `DacDual::dma_enable`.
+pub fn dma_stereo_config(channel: &mut <Dac0 as DmaRequest>::Channel, samples: &'static [u32]) {
+    channel.flag_clear(DMA_INTF_GIF);
+
+    let a = DmaParameters {
+        periph_addr: DacDual::data_address(DacAlign::Right12),
+        periph_width: DmaWidth::Bits32,
+        memory_addr: samples.as_ptr() as u32,
+        memory_width: DmaWidth::Bits32,
+        number: samples.len() as u32,
+        priority: DmaPriority::UltraHigh,
+        periph_inc: DmaIncrement::Fixed,
+        memory_inc: DmaIncrement::Increase,
+        direction: DmaDirection::MemoryToPeripheral,
+    };
+
+    channel.init(&a).unwrap();
+    channel.circulation_enable();
+    channel.channel_enable();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::sim::{self, Access};
+
+    const RCU_CTL: u32 = 0x4002_1000;
+    const RCU_CFG0: u32 = 0x4002_1004;
+    const RCU_APB1RST: u32 = 0x4002_1010;
+    const RCU_APB1EN: u32 = 0x4002_101c;
+    const DAC_CTL: u32 = 0x4000_7400;
+    const TIMER5_CTL0: u32 = 0x4000_1000;
+    const TIMER5_CTL1: u32 = 0x4000_1004;
+    const TIMER5_PSC: u32 = 0x4000_1028;
+    const TIMER5_CAR: u32 = 0x4000_102c;
+    const TIMER5_DMAINTEN: u32 = 0x4000_100c;
+    const TIMER5_INTF: u32 = 0x4000_1010;
+    const DMA1_INTF: u32 = 0x4002_0400;
+    const DMA1_INTC: u32 = 0x4002_0404;
+    const DMA1_CH2CTL: u32 = 0x4002_0430;
+    const DMA1_CH2CNT: u32 = 0x4002_0434;
+    const DMA1_CH2PADDR: u32 = 0x4002_0438;
+    const DMA1_CH2MADDR: u32 = 0x4002_043c;
+
+    #[test]
+    fn timer5_config_sequence() {
+        sim::reset();
+
+        timer5_config(&mut unsafe { Timer5::steal() }, &Clocks::from_reset());
+
+        assert_eq!(
+            sim::take_log(),
+            [
+                // 1 kHz from 32 samples: 250 ticks per sample
+                Access::Write(TIMER5_PSC, 0),
+                Access::Write(TIMER5_CAR, 249),
+                Access::Read(TIMER5_CTL0, 0),
+                Access::Write(TIMER5_CTL0, 0x1),
+            ]
+        );
+    }
+
+    #[test]
+    fn sample_player_uses_timer5_interrupt() {
+        sim::reset();
+
+        let mut player = sample_player_config(unsafe { Timer5::steal() }, &Clocks::from_reset());
+
+        assert_eq!(sim::peek(TIMER5_CAR), 249);
+        assert_eq!(sim::peek(TIMER5_DMAINTEN), 0x1);
+        assert_eq!(sim::peek(TIMER5_CTL0), 0x1);
+        // TIMER5 is interrupt 73, its enable byte is lane 1 of 0xd200_1124
+        assert_eq!(sim::peek(0xd200_1124) & 0xff00, 0x0100);
+
+        sim::preset(TIMER5_INTF, 0x1);
+        let mut first = None;
+        player.on_interrupt(|sample| first = Some(sample));
+        assert_eq!(first, Some(SINE[0]));
+    }
+
+    #[test]
+    fn chirp_steps_on_full_transfer() {
+        sim::reset();
+        // HXTAL and PLL stable, PLL selected
+        sim::preset(RCU_CTL, 1 << 17 | 1 << 25);
+        sim::preset(RCU_CFG0, 0x8);
+        let clocks = clock_config();
+        let mut channel = unsafe { Dma1::steal() }.split().ch2;
+
+        let mut chirp = chirp_config(unsafe { Timer5::steal() }, &mut channel, &clocks);
+
+        // 108 MHz / (20 Hz * 32) = 3 * 56250 ticks per sample
+        assert_eq!(sim::peek(TIMER5_PSC), 2);
+        assert_eq!(sim::peek(TIMER5_CAR), 56_249);
+        assert_eq!(sim::peek(TIMER5_CTL0), 0x81);
+        // Full transfer interrupt
+        assert_eq!(sim::peek(DMA1_CH2CTL), 0x2);
+
+        chirp_on_interrupt(&mut chirp, &mut channel);
+        assert_eq!(sim::peek(TIMER5_CAR), 56_249);
+
+        // One 50 ms cycle into the 10 s log sweep: 20 Hz * 1000^0.005 = 20.702 Hz
+        sim::preset(DMA1_INTF, 0x2 << 8);
+        chirp_on_interrupt(&mut chirp, &mut channel);
+        assert_eq!(sim::peek(TIMER5_CAR), 54_342);
+    }
+
+    #[test]
+    fn dac_config_sequence() {
+        let gpioa = unsafe { Gpioa::steal() }.split();
+        let (pa4, pa5) = (gpioa.pa4.into_analog(), gpioa.pa5.into_analog());
+        let mut timer5 = unsafe { Timer5::steal() };
+        sim::reset();
+
+        dac_config(unsafe { Dac::steal() }, pa4, pa5, &mut timer5);
+
+        assert_eq!(
+            sim::take_writes(),
+            [
+                // reset pulse
+                (RCU_APB1RST, 1 << 29),
+                (RCU_APB1RST, 0),
+                // TIMER5 clock on, TRGO on update
+                (RCU_APB1EN, 1 << 4),
+                (TIMER5_CTL1, 0),
+                (TIMER5_CTL1, 0x20),
+                // TIMER5 TRGO trigger
+                (DAC_CTL, 0),
+                (DAC_CTL, 0),
+                (DAC_CTL, 0x4),
+                // no wave
+                (DAC_CTL, 0x4),
+                (DAC_CTL, 0x4),
+                // output buffer on
+                (DAC_CTL, 0x4),
+                // channel and DMA on
+                (DAC_CTL, 0x5),
+                (DAC_CTL, 0x1005),
+            ]
+        );
+    }
+
+    #[test]
+    fn exti9_triggers_dac0() {
+        const AFIO_EXTISS2: u32 = 0x4001_0010;
+        const EXTI_EVEN: u32 = 0x4001_0404;
+        const EXTI_RTEN: u32 = 0x4001_0408;
+        let gpioa = unsafe { Gpioa::steal() }.split();
+        let (pa4, pa5) = (gpioa.pa4.into_analog(), gpioa.pa5.into_analog());
+        let (mut dac0, _) = unsafe { Dac::steal() }.split(pa4, pa5);
+        let pb9 = unsafe { Gpiob::steal() }.split().pb9;
+        let pb9 = pb9.into_pull_down_input();
+        sim::reset();
+
+        dac_exti9_trigger_config(&mut dac0, pb9, ExtiTrigger::Rising);
+
+        assert_eq!(sim::peek(AFIO_EXTISS2), 0x10);
+        assert_eq!(sim::peek(EXTI_EVEN), EXTI_9);
+        assert_eq!(sim::peek(EXTI_RTEN), EXTI_9);
+        // EXTI line 9 trigger, trigger enabled
+        assert_eq!(sim::peek(DAC_CTL), 0x34);
+    }
+
+    #[test]
+    fn led_is_active_low() {
+        const GPIOA_BOP: u32 = 0x4001_0810;
+        const GPIOA_BC: u32 = 0x4001_0814;
+        const GPIOC_BOP: u32 = 0x4001_1010;
+        const GPIOC_BC: u32 = 0x4001_1014;
+        let gpioa = unsafe { Gpioa::steal() }.split();
+        let gpioc = unsafe { Gpioc::steal() }.split();
+        sim::reset();
+
+        let mut led = led_config(gpioc.pc13, gpioa.pa1, gpioa.pa2);
+        // Off once the pins are outputs
+        assert_eq!(sim::peek(GPIOC_BOP), GPIO_PIN_13);
+        assert_eq!(sim::peek(GPIOA_BOP), GPIO_PIN_2);
+        sim::take_writes();
+
+        led_set(&mut led, LedColor::Yellow);
+
+        assert_eq!(
+            sim::take_writes(),
+            [
+                (GPIOC_BC, GPIO_PIN_13),
+                (GPIOA_BC, GPIO_PIN_1),
+                (GPIOA_BOP, GPIO_PIN_2),
+            ]
+        );
+
+        led_set(&mut led, LedColor::Off);
+        assert_eq!(
+            sim::take_writes(),
+            [
+                (GPIOC_BOP, GPIO_PIN_13),
+                (GPIOA_BOP, GPIO_PIN_1),
+                (GPIOA_BOP, GPIO_PIN_2),
+            ]
+        );
+    }
+
+    #[test]
+    fn dma_config_sequence() {
+        sim::reset();
+
+        dma_config(&mut unsafe { Dma1::steal() }.split().ch2);
+
+        assert_eq!(
+            sim::take_writes(),
+            [
+                (DMA1_INTC, 0x100),
+                (DMA1_INTC, 0x300),
+                (DMA1_INTC, 0x700),
+                (DMA1_INTC, 0xf00),
+                (DMA1_CH2PADDR, 0x4000_7408),
+                (DMA1_CH2MADDR, SINE.as_ptr() as u32),
+                (DMA1_CH2CNT, SIZE as u32),
+                // 16-bit widths, ultra high priority
+                (DMA1_CH2CTL, 0x3500),
+                (DMA1_CH2CTL, 0x3500),
+                (DMA1_CH2CTL, 0x3580),
+                (DMA1_CH2CTL, 0x3590),
+                (DMA1_CH2CTL, 0x35b0),
+                (DMA1_CH2CTL, 0x35b1),
+            ]
+        );
+    }
+
+    #[test]
+    fn dma_stereo_config_uses_32bit_concurrent_register() {
+        static SAMPLES: [u32; 2] = [0x0fff_0000, 0x0000_0fff];
+        sim::reset();
+
+        dma_stereo_config(&mut unsafe { Dma1::steal() }.split().ch2, &SAMPLES);
+
+        assert_eq!(sim::peek(DMA1_CH2PADDR), 0x4000_7420);
+        assert_eq!(sim::peek(DMA1_CH2MADDR), SAMPLES.as_ptr() as u32);
+        assert_eq!(sim::peek(DMA1_CH2CNT), 2);
+        let ctl = sim::peek(DMA1_CH2CTL);
+        // 32-bit widths, ultra high priority, memory increment, circular
+        assert_eq!(ctl & !0x1, 0x3ab0);
+        // CHEN
+        assert_eq!(ctl & 0x1, 0x1);
+    }
+}
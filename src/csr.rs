@@ -0,0 +1,94 @@
+/* Control and status registers */
+// CSRs can only be reached with dedicated instructions, so they don't go
+// through the `Bus`. On the host, reads return 0 and writes are ignored.
+
+pub const MSTATUS_MIE: u32 = 1 << 3;
+
+pub const MCAUSE_INTERRUPT: u32 = 1 << 31;
+pub const MCAUSE_EXCCODE: u32 = 0xfff;
+
+#[cfg(target_arch = "riscv32")]
+macro_rules! csr_read {
+    ($csr:literal) => {{
+        let value: u32;
+        unsafe {
+            core::arch::asm!(concat!("csrr {0}, ", $csr), out(reg) value);
+        }
+        value
+    }};
+}
+
+#[cfg(target_arch = "riscv32")]
+macro_rules! csr_write {
+    ($csr:literal, $value:expr) => {
+        unsafe {
+            core::arch::asm!(concat!("csrw ", $csr, ", {0}"), in(reg) $value);
+        }
+    };
+}
+
+#[cfg(target_arch = "riscv32")]
+macro_rules! csr_set {
+    ($csr:literal, $bits:expr) => {
+        unsafe {
+            core::arch::asm!(concat!("csrs ", $csr, ", {0}"), in(reg) $bits);
+        }
+    };
+}
+
+#[cfg(target_arch = "riscv32")]
+macro_rules! csr_clear {
+    ($csr:literal, $bits:expr) => {
+        unsafe {
+            core::arch::asm!(concat!("csrc ", $csr, ", {0}"), in(reg) $bits);
+        }
+    };
+}
+
+#[cfg(not(target_arch = "riscv32"))]
+macro_rules! csr_read {
+    ($csr:literal) => {
+        0
+    };
+}
+
+#[cfg(not(target_arch = "riscv32"))]
+macro_rules! csr_write {
+    ($csr:literal, $value:expr) => {
+        let _ = $value;
+    };
+}
+
+#[cfg(not(target_arch = "riscv32"))]
+macro_rules! csr_set {
+    ($csr:literal, $bits:expr) => {
+        let _ = $bits;
+    };
+}
+
+#[cfg(not(target_arch = "riscv32"))]
+macro_rules! csr_clear {
+    ($csr:literal, $bits:expr) => {
+        let _ = $bits;
+    };
+}
+
+pub fn mstatus_set(bits: u32) {
+    csr_set!("mstatus", bits);
+}
+
+pub fn mstatus_clear(bits: u32) {
+    csr_clear!("mstatus", bits);
+}
+
+pub fn mtvec_write(value: u32) {
+    csr_write!("mtvec", value);
+}
+
+pub fn mcause_read() -> u32 {
+    csr_read!("mcause")
+}
+
+pub fn mcycle_read() -> u32 {
+    csr_read!("mcycle")
+}
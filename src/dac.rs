@@ -1,188 +1,534 @@
-use crate::rcu::rcu_periph_reset_disable;
-use crate::rcu::rcu_periph_reset_enable;
-use crate::rcu::rcu_regidx_bit;
+use crate::dma::{Dma1Ch2, Dma1Ch3, DmaRequest};
+use crate::gpio::{Analog, PA4, PA5};
+use crate::rcu::{rcu_periph_clock_enable, rcu_periph_reset_pulse, RcuPeriph};
 use crate::register_helpers::*;
+use crate::singleton::singleton;
+use crate::timer::*;
 
 /* DAC constants */
-// Some of these have to be crate-public. A better API would hide that.
 const APB1_BUS_BASE: u32 = 0x4000_0000;
 const DAC: u32 = APB1_BUS_BASE + 0x0000_7400;
 
-pub(crate) const DAC0: u32 = 0;
-const DAC1: u32 = 1;
+const DAC_CTL: Reg32 = reg32(DAC + 0x0);
+const DAC_SWT: Reg32 = reg32(DAC + 0x4);
 
-pub(crate) const DAC0_R8DH_ADDRESS: u32 = 0x4000_7410;
+// Each group of holding registers is R12DH, L12DH, R8DH in that order.
+const DAC0_DH: u32 = DAC + 0x08;
+const DAC1_DH: u32 = DAC + 0x14;
+const DACC_DH: u32 = DAC + 0x20;
 
-const DAC_CTL: *mut u32 = reg32(DAC + 0x0);
-const DAC_SWT: *mut u32 = reg32(DAC + 0x4);
+const DH_12BIT_OFFSET: u32 = 16;
+const DH_8BIT_OFFSET: u32 = 8;
 
-const DAC0_R12DH: *mut u32 = reg32(DAC + 0x8);
-const DAC1_R12DH: *mut u32 = reg32(DAC + 0x14);
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DacAlign {
+    Right12, // data in bits 0..=11
+    Left12,  // data in bits 4..=15
+    Right8,  // data in bits 0..=7
+}
 
-pub(crate) const DAC_ALIGN_12B_R: u32 = data_align(0);
+const fn data_holding_address(dh: u32, dac_align: DacAlign) -> u32 {
+    match dac_align {
+        DacAlign::Right12 => dh,
+        DacAlign::Left12 => dh + 0x4,
+        DacAlign::Right8 => dh + 0x8,
+    }
+}
 
-const DAC0_DO: *mut u32 = reg32(DAC + 0x2c);
-const DAC1_DO: *mut u32 = reg32(DAC + 0x30);
+// Address of the concurrent holding register, e.g. as DMA peripheral address.
+pub const fn dacc_data_address(dac_align: DacAlign) -> u32 {
+    data_holding_address(DACC_DH, dac_align)
+}
 
-const DAC_CTL_DEN0: u32 = bit(0);
-const DAC_CTL_DEN1: u32 = bit(16);
+// Bits of a holding register that hold the sample.
+const fn data_mask(dac_align: DacAlign) -> u32 {
+    match dac_align {
+        DacAlign::Right12 => 0x0fff,
+        DacAlign::Left12 => 0xfff0,
+        DacAlign::Right8 => 0x00ff,
+    }
+}
 
-const DAC_CTL_DBOFF0: u32 = bit(1);
-const DAC_CTL_DBOFF1: u32 = bit(17);
+// Packs one sample per channel the way the concurrent holding registers expect.
+// Bits outside a sample's width are dropped, so they can't spill into the
+// other channel.
+pub const fn dacc_data_pack(dac_align: DacAlign, data0: u16, data1: u16) -> u32 {
+    let mask = data_mask(dac_align);
+    let (data0, data1) = (data0 as u32 & mask, data1 as u32 & mask);
+    match dac_align {
+        DacAlign::Right12 | DacAlign::Left12 => (data1 << DH_12BIT_OFFSET) | data0,
+        DacAlign::Right8 => (data1 << DH_8BIT_OFFSET) | data0,
+    }
+}
 
-const DAC_CTL_DTEN0: u32 = bit(2);
-const DAC_CTL_DTEN1: u32 = bit(18);
+// Updates both channels with a single write. Borrowing both handles makes sure
+// nobody else is using either channel.
+pub fn dac_concurrent_data_set(
+    _dac0: &mut Dac0,
+    _dac1: &mut Dac1,
+    dac_align: DacAlign,
+    data0: u16,
+    data1: u16,
+) {
+    set_register(
+        reg32(dacc_data_address(dac_align)),
+        dacc_data_pack(dac_align, data0, data1),
+    );
+}
 
-const DAC_CTL_DDMAEN0: u32 = bit(12);
-const DAC_CTL_DDMAEN1: u32 = bit(28);
+const DAC0_DO: Reg32 = reg32(DAC + 0x2c);
+const DAC1_DO: Reg32 = reg32(DAC + 0x30);
 
+// Channel 0 control bits; channel 1 uses the same bits shifted by DAC1_REG_OFFSET.
+const DAC_CTL_DEN0: u32 = bit(0);
+const DAC_CTL_DBOFF0: u32 = bit(1);
+const DAC_CTL_DTEN0: u32 = bit(2);
+const DAC_CTL_DTSEL0: u32 = bits(3, 5);
 const DAC_CTL_DWM0: u32 = bits(6, 7);
-const DAC_CTL_DWM1: u32 = bits(22, 23);
+const DAC_CTL_DWBW0: u32 = bits(8, 11);
+const DAC_CTL_DDMAEN0: u32 = bit(12);
 
 const DAC1_REG_OFFSET: u32 = 16;
 
-const DAC_CTL_DTSEL0: u32 = bits(3, 5);
-const DAC_CTL_DTSEL1: u32 = bits(19, 21);
-
 const DAC_SWT_SWTR0: u32 = bit(0);
 const DAC_SWT_SWTR1: u32 = bit(1);
 
-pub(crate) const DAC_TRIGGER_T5_TRGO:  u32 = ctl_dtsel(0);         /* TIMER5 TRGO */
-pub(crate) const DAC_TRIGGER_T2_TRGO:  u32 = ctl_dtsel(1);         /* TIMER2 TRGO */
-pub(crate) const DAC_TRIGGER_T6_TRGO:  u32 = ctl_dtsel(2);         /* TIMER6 TRGO */
-pub(crate) const DAC_TRIGGER_T4_TRGO:  u32 = ctl_dtsel(3);         /* TIMER4 TRGO */
-pub(crate) const DAC_TRIGGER_T1_TRGO:  u32 = ctl_dtsel(4);         /* TIMER1 TRGO */
-pub(crate) const DAC_TRIGGER_T3_TRGO:  u32 = ctl_dtsel(5);         /* TIMER3 TRGO */
-pub(crate) const DAC_TRIGGER_EXTI_9:   u32 = ctl_dtsel(6);         /* EXTI interrupt line9 event */
-pub(crate) const DAC_TRIGGER_SOFTWARE: u32 = ctl_dtsel(7);         /* software trigger */
+const DAC_TRIGGER_T5_TRGO:  u32 = ctl_dtsel(0);         /* TIMER5 TRGO */
+const DAC_TRIGGER_T2_TRGO:  u32 = ctl_dtsel(1);         /* TIMER2 TRGO */
+const DAC_TRIGGER_T6_TRGO:  u32 = ctl_dtsel(2);         /* TIMER6 TRGO */
+const DAC_TRIGGER_T4_TRGO:  u32 = ctl_dtsel(3);         /* TIMER4 TRGO */
+const DAC_TRIGGER_T1_TRGO:  u32 = ctl_dtsel(4);         /* TIMER1 TRGO */
+const DAC_TRIGGER_T3_TRGO:  u32 = ctl_dtsel(5);         /* TIMER3 TRGO */
+const DAC_TRIGGER_EXTI_9:   u32 = ctl_dtsel(6);         /* EXTI interrupt line9 event */
+const DAC_TRIGGER_SOFTWARE: u32 = ctl_dtsel(7);         /* software trigger */
+
+// Where a channel's conversions are triggered from. Timer triggers borrow the
+// timer's handle, so only its owner can make it a trigger source.
+pub enum DacTrigger<'a> {
+    Timer1(&'a mut Timer1),
+    Timer2(&'a mut Timer2),
+    Timer3(&'a mut Timer3),
+    Timer4(&'a mut Timer4),
+    Timer5(&'a mut Timer5),
+    Timer6(&'a mut Timer6),
+    Exti9,
+    Software,
+}
+
+// Returns the DTSEL value for `trigger`. A timer gets its clock enabled and
+// is set to emit TRGO on every update event.
+fn dac_trigger_source(trigger: DacTrigger) -> u32 {
+    let (timer_periph, rcu_periph, source) = match trigger {
+        DacTrigger::Timer1(_) => (TIMER1, RcuPeriph::Timer1, DAC_TRIGGER_T1_TRGO),
+        DacTrigger::Timer2(_) => (TIMER2, RcuPeriph::Timer2, DAC_TRIGGER_T2_TRGO),
+        DacTrigger::Timer3(_) => (TIMER3, RcuPeriph::Timer3, DAC_TRIGGER_T3_TRGO),
+        DacTrigger::Timer4(_) => (TIMER4, RcuPeriph::Timer4, DAC_TRIGGER_T4_TRGO),
+        DacTrigger::Timer5(_) => (TIMER5, RcuPeriph::Timer5, DAC_TRIGGER_T5_TRGO),
+        DacTrigger::Timer6(_) => (TIMER6, RcuPeriph::Timer6, DAC_TRIGGER_T6_TRGO),
+        DacTrigger::Exti9 => return DAC_TRIGGER_EXTI_9,
+        DacTrigger::Software => return DAC_TRIGGER_SOFTWARE,
+    };
+    rcu_periph_clock_enable(rcu_periph);
+    timer_master_output_trigger_source_select(timer_periph, TIMER_TRI_OUT_SRC_UPDATE);
+    source
+}
 
 const fn ctl_dwm(regval: u32) -> u32 {
     bits(6, 7) & (regval << 6)
 }
 
-pub(crate) const DAC_WAVE_DISABLE: u32 = ctl_dwm(0);
+const fn ctl_dwbw(regval: u32) -> u32 {
+    bits(8, 11) & (regval << 8)
+}
+
+const DAC_WAVE_DISABLE: u32 = ctl_dwm(0);
+const DAC_WAVE_MODE_LFSR: u32 = ctl_dwm(1);
+const DAC_WAVE_MODE_TRIANGLE: u32 = ctl_dwm(2);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WaveBitWidth {
+    Width1 = 0,
+    Width2,
+    Width3,
+    Width4,
+    Width5,
+    Width6,
+    Width7,
+    Width8,
+    Width9,
+    Width10,
+    Width11,
+    Width12,
+}
+
+// The generated wave is added to the holding register value on every trigger,
+// so the channel needs a trigger source and `trigger_enable` to produce it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WaveMode {
+    Disabled,
+    // Pseudo-random noise on the lowest `unmasked_bits` bits.
+    Lfsr { unmasked_bits: WaveBitWidth },
+    // Triangle from 0 up to 2^amplitude - 1 and back down.
+    Triangle { amplitude: WaveBitWidth },
+}
+
+const fn wave_mode_bits(wave_mode: WaveMode) -> u32 {
+    match wave_mode {
+        WaveMode::Disabled => DAC_WAVE_DISABLE,
+        WaveMode::Lfsr { unmasked_bits } => DAC_WAVE_MODE_LFSR | ctl_dwbw(unmasked_bits as u32),
+        WaveMode::Triangle { amplitude } => DAC_WAVE_MODE_TRIANGLE | ctl_dwbw(amplitude as u32),
+    }
+}
 
 const fn ctl_dtsel(reg_val: u32) -> u32 {
     bits(3, 5) & (reg_val << 3)
 }
 
-pub fn dac_enable(dac_periph: u32) {
-    if dac_periph == DAC0 {
-        set_bits(DAC_CTL, DAC_CTL_DEN0);
-    } else if dac_periph == DAC1 {
-        set_bits(DAC_CTL, DAC_CTL_DEN1);
+// Owns the whole DAC; split it into its two channels to use them.
+singleton!(Dac, DAC_TAKEN, "the DAC, `Dac0` or `Dac1`");
+
+impl Dac {
+    // Resets both channels to their power-on state.
+    pub fn deinit(&mut self) {
+        dac_deinit();
     }
-}
 
-pub fn dac_disable(dac_periph: u32) {
-    if dac_periph == DAC0 {
-        reset_bits(DAC_CTL, DAC_CTL_DEN0);
-    } else if dac_periph == DAC1 {
-        reset_bits(DAC_CTL, DAC_CTL_DEN1);
+    // Channel 0 drives PA4 and channel 1 PA5; both pins have to be in analog
+    // mode, and stay there as long as the channels exist.
+    pub fn split(self, pa4: PA4<Analog>, pa5: PA5<Analog>) -> (Dac0, Dac1) {
+        (Dac0 { _pin: pa4 }, Dac1 { _pin: pa5 })
     }
 }
 
-pub fn dac_dma_enable(dac_periph: u32) {
-    if dac_periph == DAC0 {
-        set_bits(DAC_CTL, DAC_CTL_DDMAEN0);
-    } else if dac_periph == DAC1 {
-        set_bits(DAC_CTL, DAC_CTL_DDMAEN1);
-    }
+macro_rules! dac_channel {
+    ($Dacx:ident, $Pin:ty, $offset:expr, $swtr:expr, $dh:expr, $do:expr) => {
+        pub struct $Dacx {
+            _pin: $Pin,
+        }
+
+        impl $Dacx {
+            // Address of this channel's holding register, e.g. as DMA peripheral address.
+            pub const fn data_address(dac_align: DacAlign) -> u32 {
+                data_holding_address($dh, dac_align)
+            }
+
+            pub fn enable(&mut self) {
+                set_bits(DAC_CTL, DAC_CTL_DEN0 << $offset);
+            }
+
+            pub fn disable(&mut self) {
+                reset_bits(DAC_CTL, DAC_CTL_DEN0 << $offset);
+            }
+
+            pub fn dma_enable(&mut self) {
+                set_bits(DAC_CTL, DAC_CTL_DDMAEN0 << $offset);
+            }
+
+            pub fn dma_disable(&mut self) {
+                reset_bits(DAC_CTL, DAC_CTL_DDMAEN0 << $offset);
+            }
+
+            pub fn output_buffer_enable(&mut self) {
+                reset_bits(DAC_CTL, DAC_CTL_DBOFF0 << $offset);
+            }
+
+            pub fn output_buffer_disable(&mut self) {
+                set_bits(DAC_CTL, DAC_CTL_DBOFF0 << $offset);
+            }
+
+            pub fn output_value_get(&self) -> u16 {
+                read_register($do) as u16
+            }
+
+            pub fn data_set(&mut self, dac_align: DacAlign, data: u16) {
+                set_register(reg32(Self::data_address(dac_align)), data as u32);
+            }
+
+            pub fn trigger_enable(&mut self) {
+                set_bits(DAC_CTL, DAC_CTL_DTEN0 << $offset);
+            }
+
+            pub fn trigger_disable(&mut self) {
+                reset_bits(DAC_CTL, DAC_CTL_DTEN0 << $offset);
+            }
+
+            pub fn wave_mode_config(&mut self, wave_mode: WaveMode) {
+                reset_bits(DAC_CTL, (DAC_CTL_DWM0 | DAC_CTL_DWBW0) << $offset);
+                set_bits(DAC_CTL, wave_mode_bits(wave_mode) << $offset);
+            }
+
+            fn trigger_source_config(&mut self, triggersource: u32) {
+                reset_bits(DAC_CTL, DAC_CTL_DTSEL0 << $offset);
+                set_bits(DAC_CTL, triggersource << $offset);
+            }
+
+            pub fn software_trigger_enable(&mut self) {
+                set_bits(DAC_SWT, $swtr);
+            }
+
+            // Selects `trigger` and enables triggering.
+            pub fn trigger_config(&mut self, trigger: DacTrigger) {
+                self.trigger_source_config(dac_trigger_source(trigger));
+                self.trigger_enable();
+            }
+        }
+    };
 }
 
-pub fn dac_dma_disable(dac_periph: u32) {
-    if dac_periph == DAC0 {
-        reset_bits(DAC_CTL, DAC_CTL_DDMAEN0);
-    } else if dac_periph == DAC1 {
-        reset_bits(DAC_CTL, DAC_CTL_DDMAEN1);
-    }
+dac_channel!(Dac0, PA4<Analog>, 0, DAC_SWT_SWTR0, DAC0_DH, DAC0_DO);
+dac_channel!(
+    Dac1,
+    PA5<Analog>,
+    DAC1_REG_OFFSET,
+    DAC_SWT_SWTR1,
+    DAC1_DH,
+    DAC1_DO
+);
+
+// With DDMAENx set, each trigger requests a transfer on these channels.
+impl DmaRequest for Dac0 {
+    type Channel = Dma1Ch2;
 }
 
-pub fn dac_output_buffer_enable(dac_periph: u32) {
-    if dac_periph == DAC0 {
-        reset_bits(DAC_CTL, DAC_CTL_DBOFF0);
-    } else if dac_periph == DAC1 {
-        reset_bits(DAC_CTL, DAC_CTL_DBOFF1);
-    }
+impl DmaRequest for Dac1 {
+    type Channel = Dma1Ch3;
 }
 
-pub fn dac_output_buffer_disable(dac_periph: u32) {
-    if dac_periph == DAC0 {
-        set_bits(DAC_CTL, DAC_CTL_DBOFF0);
-    } else if dac_periph == DAC1 {
-        set_bits(DAC_CTL, DAC_CTL_DBOFF1);
-    }
+// Both channels driven as a pair, e.g. for I/Q or X/Y output. Data goes through
+// the concurrent holding registers and control bits of both channels are
+// changed with a single register write, so the channels never drift apart.
+pub struct DacDual {
+    dac0: Dac0,
+    dac1: Dac1,
 }
 
-pub fn dac_output_value_get(dac_periph: u32) -> u32 {
-    match dac_periph {
-        DAC0 => read_register(DAC0_DO),
-        DAC1 => read_register(DAC1_DO),
-        _ => 0,
-    }
+// Channel 0 control bits plus the matching channel 1 bits.
+const fn both(ctl_bits: u32) -> u32 {
+    ctl_bits | (ctl_bits << DAC1_REG_OFFSET)
 }
 
-pub fn dac_data_set(dac_periph: u32, dac_align: u32, data: u16) {
-    if dac_periph == DAC0 {
-        match dac_align {
-            DAC_ALIGN_12B_R => set_register(DAC0_R12DH, data as u32),
-            _ => unimplemented!(),
-        };
-    } else if dac_periph == DAC1 {
-        match dac_align {
-            DAC_ALIGN_12B_R => set_register(DAC1_R12DH, data as u32),
-            _ => unimplemented!(),
-        }
+impl DacDual {
+    pub fn new(dac0: Dac0, dac1: Dac1) -> DacDual {
+        DacDual { dac0, dac1 }
     }
-}
 
-pub fn dac_trigger_enable(dac_periph: u32) {
-    if dac_periph == DAC0 {
-        set_bits(DAC_CTL, DAC_CTL_DTEN0);
-    } else if dac_periph == DAC1 {
-        set_bits(DAC_CTL, DAC_CTL_DTEN1);
+    pub fn release(self) -> (Dac0, Dac1) {
+        (self.dac0, self.dac1)
     }
-}
 
-pub fn dac_trigger_disable(dac_periph: u32) {
-    if dac_periph == DAC0 {
-        reset_bits(DAC_CTL, DAC_CTL_DTEN0);
-    } else if dac_periph == DAC1 {
-        reset_bits(DAC_CTL, DAC_CTL_DTEN1);
+    // Address of the concurrent holding register. With `DacAlign::Right12` or
+    // `DacAlign::Left12` a single 32-bit DMA stream of `dacc_data_pack`ed
+    // samples feeds both channels.
+    pub const fn data_address(dac_align: DacAlign) -> u32 {
+        dacc_data_address(dac_align)
     }
-}
 
-pub fn dac_wave_mode_config(dac_periph: u32, wave_mode: u32) {
-    if dac_periph == DAC0 {
-        reset_bits(DAC_CTL, DAC_CTL_DWM0);
-        set_bits(DAC_CTL, wave_mode);
-    } else if dac_periph == DAC1 {
-        reset_bits(DAC_CTL, DAC_CTL_DWM1);
-        set_bits(DAC_CTL, wave_mode << DAC1_REG_OFFSET);
+    pub fn enable(&mut self) {
+        set_bits(DAC_CTL, both(DAC_CTL_DEN0));
     }
-}
 
-pub fn dac_trigger_source_config(dac_periph: u32, triggersource: u32) {
-    if dac_periph == DAC0 {
-        reset_bits(DAC_CTL, DAC_CTL_DTSEL0);
-        set_bits(DAC_CTL, triggersource);
-    } else if dac_periph == DAC1 {
-        reset_bits(DAC_CTL, DAC_CTL_DTSEL1);
-        set_bits(DAC_CTL, triggersource << DAC1_REG_OFFSET);
+    pub fn disable(&mut self) {
+        reset_bits(DAC_CTL, both(DAC_CTL_DEN0));
+    }
+
+    pub fn output_buffer_enable(&mut self) {
+        reset_bits(DAC_CTL, both(DAC_CTL_DBOFF0));
+    }
+
+    pub fn output_buffer_disable(&mut self) {
+        set_bits(DAC_CTL, both(DAC_CTL_DBOFF0));
+    }
+
+    // Only channel 0 requests DMA: each request moves one packed sample into
+    // the concurrent register, which updates both channels.
+    pub fn dma_enable(&mut self) {
+        self.dac0.dma_enable();
+    }
+
+    pub fn dma_disable(&mut self) {
+        self.dac0.dma_disable();
+    }
+
+    pub fn data_set(&mut self, dac_align: DacAlign, data0: u16, data1: u16) {
+        dac_concurrent_data_set(&mut self.dac0, &mut self.dac1, dac_align, data0, data1);
     }
-}
 
-pub fn dac_software_trigger_enable(dac_periph: u32) {
-    if dac_periph == DAC0 {
-        set_bits(DAC_SWT, DAC_SWT_SWTR0);
-    } else if dac_periph == DAC1 {
-        set_bits(DAC_SWT, DAC_SWT_SWTR1);
+    pub fn output_value_get(&self) -> (u16, u16) {
+        (self.dac0.output_value_get(), self.dac1.output_value_get())
+    }
+
+    pub fn trigger_enable(&mut self) {
+        set_bits(DAC_CTL, both(DAC_CTL_DTEN0));
+    }
+
+    pub fn trigger_disable(&mut self) {
+        reset_bits(DAC_CTL, both(DAC_CTL_DTEN0));
+    }
+
+    fn trigger_source_config(&mut self, triggersource: u32) {
+        let mut ctl = read_register(DAC_CTL);
+        ctl &= !both(DAC_CTL_DTSEL0);
+        ctl |= both(triggersource);
+        set_register(DAC_CTL, ctl);
+    }
+
+    // Latches both holding register values into the outputs at the same time.
+    pub fn software_trigger_enable(&mut self) {
+        set_bits(DAC_SWT, DAC_SWT_SWTR0 | DAC_SWT_SWTR1);
+    }
+
+    pub fn trigger_config(&mut self, trigger: DacTrigger) {
+        self.trigger_source_config(dac_trigger_source(trigger));
+        self.trigger_enable();
     }
 }
 
-pub fn dac_deinit() {
-    const APB1RST_REG_OFFSET: u32 = 0x10;
-    let rcu_dacrst: u32 = rcu_regidx_bit(APB1RST_REG_OFFSET, 29u32);
-    rcu_periph_reset_enable(rcu_dacrst);
-    rcu_periph_reset_disable(rcu_dacrst);
+fn dac_deinit() {
+    rcu_periph_reset_pulse(RcuPeriph::Dac);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::sim;
+
+    const CTL: u32 = 0x4000_7400;
+    const SWT: u32 = 0x4000_7404;
+
+    fn analog_pins() -> (PA4<Analog>, PA5<Analog>) {
+        let gpioa = unsafe { crate::gpio::Gpioa::steal() }.split();
+        (gpioa.pa4.into_analog(), gpioa.pa5.into_analog())
+    }
+
+    #[test]
+    fn take_only_once() {
+        assert!(Dac::take().is_some());
+        assert!(Dac::take().is_none());
+    }
+
+    #[test]
+    fn dac1_uses_upper_half_of_ctl() {
+        let (pa4, pa5) = analog_pins();
+        sim::reset();
+        let (_, mut dac1) = unsafe { Dac::steal() }.split(pa4, pa5);
+
+        dac1.trigger_source_config(DAC_TRIGGER_T2_TRGO);
+        dac1.enable();
+        dac1.dma_enable();
+        dac1.software_trigger_enable();
+        dac1.data_set(DacAlign::Right12, 0xabc);
+
+        assert_eq!(sim::peek(CTL), (1 << 19) | (1 << 16) | (1 << 28));
+        assert_eq!(sim::peek(SWT), 1 << 1);
+        assert_eq!(sim::peek(0x4000_7414), 0xabc);
+    }
+
+    #[test]
+    fn timer_trigger_enables_timer_trgo() {
+        const RCU_APB1EN: u32 = 0x4002_101c;
+        const TIMER2_CTL1: u32 = 0x4000_0404;
+        let (pa4, pa5) = analog_pins();
+        sim::reset();
+        let (mut dac0, mut dac1) = unsafe { Dac::steal() }.split(pa4, pa5);
+        let mut timer2 = unsafe { Timer2::steal() };
+
+        dac0.trigger_config(DacTrigger::Timer2(&mut timer2));
+
+        assert_eq!(sim::peek(RCU_APB1EN), 1 << 1);
+        assert_eq!(sim::peek(TIMER2_CTL1), 0x20);
+        // TIMER2 TRGO selected, trigger enabled
+        assert_eq!(sim::peek(CTL), 0xc);
+
+        sim::reset();
+        dac1.trigger_config(DacTrigger::Software);
+        assert_eq!(
+            sim::take_writes(),
+            [(CTL, 0), (CTL, 0x38 << 16), (CTL, 0x3c << 16)]
+        );
+    }
+
+    #[test]
+    fn holding_register_addresses() {
+        assert_eq!(Dac0::data_address(DacAlign::Right12), 0x4000_7408);
+        assert_eq!(Dac0::data_address(DacAlign::Left12), 0x4000_740c);
+        assert_eq!(Dac0::data_address(DacAlign::Right8), 0x4000_7410);
+        assert_eq!(Dac1::data_address(DacAlign::Right12), 0x4000_7414);
+        assert_eq!(Dac1::data_address(DacAlign::Left12), 0x4000_7418);
+        assert_eq!(Dac1::data_address(DacAlign::Right8), 0x4000_741c);
+        assert_eq!(dacc_data_address(DacAlign::Right12), 0x4000_7420);
+        assert_eq!(dacc_data_address(DacAlign::Left12), 0x4000_7424);
+        assert_eq!(dacc_data_address(DacAlign::Right8), 0x4000_7428);
+    }
+
+    #[test]
+    fn concurrent_data_set_packs_both_channels() {
+        let (pa4, pa5) = analog_pins();
+        sim::reset();
+        let (mut dac0, mut dac1) = unsafe { Dac::steal() }.split(pa4, pa5);
+
+        dac_concurrent_data_set(&mut dac0, &mut dac1, DacAlign::Left12, 0x1230, 0xabc0);
+        dac_concurrent_data_set(&mut dac0, &mut dac1, DacAlign::Right8, 0x12, 0xab);
+
+        assert_eq!(sim::peek(0x4000_7424), 0xabc0_1230);
+        assert_eq!(sim::peek(0x4000_7428), 0xab12);
+    }
+
+    #[test]
+    fn pack_drops_out_of_range_bits() {
+        assert_eq!(dacc_data_pack(DacAlign::Right8, 0x1ff, 0x0ab), 0xabff);
+        assert_eq!(
+            dacc_data_pack(DacAlign::Right12, 0xffff, 0x1abc),
+            0x0abc_0fff
+        );
+        assert_eq!(
+            dacc_data_pack(DacAlign::Left12, 0x123f, 0xabcf),
+            0xabc0_1230
+        );
+    }
+
+    #[test]
+    fn wave_modes_program_dwm_and_dwbw() {
+        let (pa4, pa5) = analog_pins();
+        sim::reset();
+        let (mut dac0, mut dac1) = unsafe { Dac::steal() }.split(pa4, pa5);
+
+        dac0.wave_mode_config(WaveMode::Lfsr {
+            unmasked_bits: WaveBitWidth::Width4,
+        });
+        dac1.wave_mode_config(WaveMode::Triangle {
+            amplitude: WaveBitWidth::Width12,
+        });
+        assert_eq!(
+            sim::peek(CTL),
+            (0x1 << 6 | 0x3 << 8) | (0x2 << 22 | 0xb << 24)
+        );
+
+        dac0.wave_mode_config(WaveMode::Disabled);
+        assert_eq!(sim::peek(CTL), 0x2 << 22 | 0xb << 24);
+    }
+
+    #[test]
+    fn dual_updates_both_channels_in_one_write() {
+        let (pa4, pa5) = analog_pins();
+        sim::reset();
+        let (dac0, dac1) = unsafe { Dac::steal() }.split(pa4, pa5);
+        let mut dual = DacDual::new(dac0, dac1);
+
+        dual.trigger_source_config(DAC_TRIGGER_SOFTWARE);
+        dual.trigger_enable();
+        dual.enable();
+        dual.data_set(DacAlign::Right12, 0x123, 0x456);
+        dual.software_trigger_enable();
+
+        assert_eq!(
+            sim::take_writes(),
+            [
+                (CTL, 0x38 | 0x38 << 16),
+                (CTL, 0x3c | 0x3c << 16),
+                (CTL, 0x3d | 0x3d << 16),
+                (0x4000_7420, 0x0456_0123),
+                (SWT, 0x3),
+            ]
+        );
+    }
 }
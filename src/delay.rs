@@ -0,0 +1,58 @@
+#[cfg(target_arch = "riscv32")]
+use crate::csr::mcycle_read;
+use crate::rcu::Clocks;
+
+// mcycle is read as 32 bits, so long delays are waited out in pieces that are
+// well inside its wrap-around.
+const MAX_WAIT_CYCLES: u64 = 1 << 31;
+
+// Busy-wait delays, counted on the core's mcycle counter which runs at hclk.
+pub struct Delay {
+    cycles_per_us: u32,
+}
+
+impl Delay {
+    pub fn new(clocks: &Clocks) -> Delay {
+        Delay {
+            cycles_per_us: clocks.hclk() / 1_000_000,
+        }
+    }
+
+    pub fn delay_us(&mut self, us: u32) {
+        let mut cycles = us as u64 * self.cycles_per_us as u64;
+        while cycles > 0 {
+            let chunk = cycles.min(MAX_WAIT_CYCLES);
+            wait_cycles(chunk as u32);
+            cycles -= chunk;
+        }
+    }
+
+    pub fn delay_ms(&mut self, ms: u32) {
+        for _ in 0..ms {
+            self.delay_us(1000);
+        }
+    }
+}
+
+#[cfg(target_arch = "riscv32")]
+fn wait_cycles(cycles: u32) {
+    let start = mcycle_read();
+    while mcycle_read().wrapping_sub(start) < cycles {}
+}
+
+// The host simulation has no notion of time, so there is nothing to wait for.
+#[cfg(not(target_arch = "riscv32"))]
+fn wait_cycles(_cycles: u32) {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn long_delays_do_not_overflow() {
+        // 8 cycles per us from the reset clocks: u32::MAX us is 2^35 cycles.
+        let mut delay = Delay::new(&Clocks::from_reset());
+        delay.delay_us(u32::MAX);
+        delay.delay_ms(1);
+    }
+}
@@ -1,4 +1,7 @@
 use crate::register_helpers::*;
+use crate::singleton::singleton;
+use core::ops::BitOr;
+use core::sync::atomic::{compiler_fence, Ordering};
 
 const fn chctl_pwidth(regval: u32) -> u32 {
     bits(8, 9) & (regval << 8)
@@ -17,20 +20,77 @@ pub const DMA_INTF_FTFIF: u32 = bit(1);
 pub const DMA_INTF_HTFIF: u32 = bit(2);
 pub const DMA_INTF_ERRIF: u32 = bit(3);
 
-pub const DMA_PERIPHERAL_WIDTH_8BIT: u32 = chctl_pwidth(0);
+// A set of channel interrupts, e.g. `DMA_INT_HTF | DMA_INT_FTF`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct DmaInterrupts(u32);
 
-pub const DMA_MEMORY_WIDTH_8BIT: u32 = chctl_mwidth(0);
+pub const DMA_INT_FTF: DmaInterrupts = DmaInterrupts(DMA_CHXCTL_FTFIE);
+pub const DMA_INT_HTF: DmaInterrupts = DmaInterrupts(DMA_CHXCTL_HTFIE);
+pub const DMA_INT_ERR: DmaInterrupts = DmaInterrupts(DMA_CHXCTL_ERRIE);
 
-pub const DMA_PRIORITY_ULTRA_HIGH: u32 = chctl_prio(3);
+impl DmaInterrupts {
+    // The enable bits in CHCTL; nothing else can be set through a set.
+    const fn bits(self) -> u32 {
+        self.0 & (DMA_CHXCTL_FTFIE | DMA_CHXCTL_HTFIE | DMA_CHXCTL_ERRIE)
+    }
+}
+
+impl BitOr for DmaInterrupts {
+    type Output = DmaInterrupts;
+
+    fn bitor(self, other: DmaInterrupts) -> DmaInterrupts {
+        DmaInterrupts(self.0 | other.0)
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DmaWidth {
+    Bits8 = 0,
+    Bits16 = 1,
+    Bits32 = 2,
+}
+
+impl DmaWidth {
+    pub const fn bytes(self) -> u32 {
+        1 << self as u32
+    }
+}
 
-pub const DMA_PERIPH_INCREASE_DISABLE: u8 = 0x0;
-pub const DMA_PERIPH_INCREASE_ENABLE: u8 = 0x1;
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DmaPriority {
+    Low = 0,
+    Medium = 1,
+    High = 2,
+    UltraHigh = 3,
+}
 
-pub const DMA_MEMORY_INCREASE_DISABLE: u8 = 0x0;
-pub const DMA_MEMORY_INCREASE_ENABLE: u8 = 0x1;
+// Whether an address advances by one data width after every transfer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DmaIncrement {
+    Fixed,
+    Increase,
+}
 
-pub const DMA_PERIPHERAL_TO_MEMORY: u8 = 0x0;
-pub const DMA_MEMORY_TO_PERIPHERAL: u8 = 0x1;
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DmaDirection {
+    PeripheralToMemory,
+    MemoryToPeripheral,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DmaError {
+    // There is no DMA controller at this address.
+    InvalidPeriph,
+    // The controller has no such channel.
+    InvalidChannel,
+    // Zero transfers, or more than CHCNT can hold.
+    InvalidCount,
+    // An address is not a multiple of its data width.
+    UnalignedPeriphAddress,
+    UnalignedMemoryAddress,
+    // A bus error aborted the transfer.
+    TransferError,
+}
 
 const DMA_CHXCTL_PNAGA: u32 = bit(6);
 const DMA_CHXCTL_MNAGA: u32 = bit(7);
@@ -38,33 +98,34 @@ const DMA_CHXCTL_MNAGA: u32 = bit(7);
 const AHB1_BUS_BASE: u32 = 0x4001_8000;
 const DMA_BASE: u32 = AHB1_BUS_BASE + 0x0000_8000;
 
+pub const DMA0: u32 = DMA_BASE;
 pub const DMA1: u32 = DMA_BASE + 0x0400;
 
 pub struct DmaParameters {
-    pub periph_addr: u32,  // peripheral base address
-    pub periph_width: u32, // transfer data size of peripheral
-    pub memory_addr: u32,  // memory base address
-    pub memory_width: u32, // transfer data size of memory
-    pub number: u32,       // channel transfer number
-    pub priority: u32,     // channel priority number
-    pub periph_inc: u8,    // peripheral increasing mode
-    pub memory_inc: u8,    // memory increasing mode
-    pub direction: u8,     // channel data transfer direction
-}
-
-const fn dma_chpaddr(dma: u32, channel: &DmaChannel) -> *mut u32 {
+    pub periph_addr: u32,         // peripheral base address
+    pub periph_width: DmaWidth,   // transfer data size of peripheral
+    pub memory_addr: u32,         // memory base address
+    pub memory_width: DmaWidth,   // transfer data size of memory
+    pub number: u32,              // channel transfer number
+    pub priority: DmaPriority,    // channel priority number
+    pub periph_inc: DmaIncrement, // peripheral increasing mode
+    pub memory_inc: DmaIncrement, // memory increasing mode
+    pub direction: DmaDirection,  // channel data transfer direction
+}
+
+const fn dma_chpaddr(dma: u32, channel: &DmaChannel) -> Reg32 {
     reg32((dma + 0x10) + 0x14 * (*channel as u32))
 }
 
-const fn dma_chmaddr(dma: u32, channel: &DmaChannel) -> *mut u32 {
+const fn dma_chmaddr(dma: u32, channel: &DmaChannel) -> Reg32 {
     reg32((dma + 0x14) + 0x14 * (*channel as u32))
 }
 
-const fn dma_chcnt(dma: u32, channel: &DmaChannel) -> *mut u32 {
-    reg32((dma + 0x0) + 0x14 * (*channel as u32))
+const fn dma_chcnt(dma: u32, channel: &DmaChannel) -> Reg32 {
+    reg32((dma + 0x0c) + 0x14 * (*channel as u32))
 }
 
-const fn dma_chctl(dma: u32, channel: &DmaChannel) -> *mut u32 {
+const fn dma_chctl(dma: u32, channel: &DmaChannel) -> Reg32 {
     reg32((dma + 0x08) + 0x14 * (*channel as u32))
 }
 
@@ -75,11 +136,15 @@ const DMA_CHXCTL_PRIO: u32 = bits(12, 13);
 const DMA_CHXCNT_CNT: u32 = bits(0, 15);
 const DMA_CHANNEL_CNT_MASK: u32 = DMA_CHXCNT_CNT;
 
-const DMA_CHXCTL_CHEN: u32 = bit(1);
+const DMA_CHXCTL_CHEN: u32 = bit(0);
+const DMA_CHXCTL_FTFIE: u32 = bit(1);
+const DMA_CHXCTL_HTFIE: u32 = bit(2);
+const DMA_CHXCTL_ERRIE: u32 = bit(3);
 const DMA_CHXCTL_DIR: u32 = bit(4);
 const DMA_CHXCTL_CMEN: u32 = bit(5);
+const DMA_CHXCTL_M2M: u32 = bit(14);
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum DmaChannel {
     DmaCh0 = 0,
     DmaCh1,
@@ -90,10 +155,28 @@ pub enum DmaChannel {
     DmaCh6,
 }
 
-pub fn dma_init(dma_periph: u32, channelx: &DmaChannel, init_struct: &DmaParameters) -> Option<()> {
-    if dma_periph_and_channel_check(dma_periph, channelx).is_none() {
-        return None;
+fn dma_init(
+    dma_periph: u32,
+    channelx: &DmaChannel,
+    init_struct: &DmaParameters,
+) -> Result<(), DmaError> {
+    dma_periph_and_channel_check(dma_periph, channelx)?;
+    if init_struct.number == 0 || init_struct.number > DMA_CHANNEL_CNT_MASK {
+        return Err(DmaError::InvalidCount);
     }
+    if !init_struct
+        .periph_addr
+        .is_multiple_of(init_struct.periph_width.bytes())
+    {
+        return Err(DmaError::UnalignedPeriphAddress);
+    }
+    if !init_struct
+        .memory_addr
+        .is_multiple_of(init_struct.memory_width.bytes())
+    {
+        return Err(DmaError::UnalignedMemoryAddress);
+    }
+
     set_register(dma_chpaddr(dma_periph, channelx), init_struct.periph_addr);
     set_register(dma_chmaddr(dma_periph, channelx), init_struct.memory_addr);
 
@@ -104,67 +187,760 @@ pub fn dma_init(dma_periph: u32, channelx: &DmaChannel, init_struct: &DmaParamet
 
     let mut ctl: u32 = read_register(dma_chctl(dma_periph, channelx));
     ctl &= !(DMA_CHXCTL_PWIDTH | DMA_CHXCTL_MWIDTH | DMA_CHXCTL_PRIO);
-    ctl |= (init_struct.periph_width | init_struct.memory_width | init_struct.priority);
+    ctl |= chctl_pwidth(init_struct.periph_width as u32)
+        | chctl_mwidth(init_struct.memory_width as u32)
+        | chctl_prio(init_struct.priority as u32);
     set_register(dma_chctl(dma_periph, channelx), ctl);
 
-    if init_struct.periph_inc == DMA_PERIPH_INCREASE_ENABLE {
-        set_bits(dma_chctl(dma_periph, channelx), DMA_CHXCTL_PNAGA);
-    } else {
-        reset_bits(dma_chctl(dma_periph, channelx), DMA_CHXCTL_PNAGA);
+    match init_struct.periph_inc {
+        DmaIncrement::Increase => set_bits(dma_chctl(dma_periph, channelx), DMA_CHXCTL_PNAGA),
+        DmaIncrement::Fixed => reset_bits(dma_chctl(dma_periph, channelx), DMA_CHXCTL_PNAGA),
     }
-    if init_struct.memory_inc == DMA_MEMORY_INCREASE_ENABLE {
-        set_bits(dma_chctl(dma_periph, channelx), DMA_CHXCTL_MNAGA);
-    } else {
-        reset_bits(dma_chctl(dma_periph, channelx), DMA_CHXCTL_MNAGA);
+    match init_struct.memory_inc {
+        DmaIncrement::Increase => set_bits(dma_chctl(dma_periph, channelx), DMA_CHXCTL_MNAGA),
+        DmaIncrement::Fixed => reset_bits(dma_chctl(dma_periph, channelx), DMA_CHXCTL_MNAGA),
     }
-    if init_struct.direction == DMA_PERIPHERAL_TO_MEMORY {
-        reset_bits(dma_chctl(dma_periph, channelx), DMA_CHXCTL_DIR);
-    } else {
-        set_bits(dma_chctl(dma_periph, channelx), DMA_CHXCTL_DIR);
+    match init_struct.direction {
+        DmaDirection::PeripheralToMemory => {
+            reset_bits(dma_chctl(dma_periph, channelx), DMA_CHXCTL_DIR)
+        }
+        DmaDirection::MemoryToPeripheral => {
+            set_bits(dma_chctl(dma_periph, channelx), DMA_CHXCTL_DIR)
+        }
     }
-    Some(())
+    Ok(())
 }
 
-pub fn dma_periph_and_channel_check(dma_periph: u32, channelx: &DmaChannel) -> Option<()> {
-    return if dma_periph == DMA1 {
-        match channelx {
+fn dma_periph_and_channel_check(dma_periph: u32, channelx: &DmaChannel) -> Result<(), DmaError> {
+    match dma_periph {
+        // DMA0 has all seven channels.
+        DMA0 => Ok(()),
+        DMA1 => match channelx {
             DmaChannel::DmaCh0
             | DmaChannel::DmaCh1
             | DmaChannel::DmaCh2
             | DmaChannel::DmaCh3
-            | DmaChannel::DmaCh4 => Some(()),
-            _ => None,
-        }
-    } else {
-        Some(())
-    };
+            | DmaChannel::DmaCh4 => Ok(()),
+            _ => Err(DmaError::InvalidChannel),
+        },
+        _ => Err(DmaError::InvalidPeriph),
+    }
 }
 
 fn dma_flag_add(flag: u32, shift: u32) -> u32 {
     flag << (4 * shift)
 }
 
-fn dma_intc(dmax: u32) -> *mut u32 {
+fn dma_intf(dmax: u32) -> Reg32 {
+    reg32(dmax + 0x0)
+}
+
+fn dma_intc(dmax: u32) -> Reg32 {
     reg32(dmax + 0x4)
 }
 
-pub fn dma_flag_clear(dma_periph: u32, channelx: &DmaChannel, flag: u32) {
-    set_bits(dma_intc(dma_periph), dma_flag_add(flag, (*channelx as u32)));
+fn dma_flag_get(dma_periph: u32, channelx: &DmaChannel, flag: u32) -> bool {
+    read_register(dma_intf(dma_periph)) & dma_flag_add(flag, *channelx as u32) != 0
 }
 
-pub fn dma_circulation_enable(dma_periph: u32, channelx: &DmaChannel) -> Option<()> {
-    if dma_periph_and_channel_check(dma_periph, channelx).is_none() {
-        return None
-    }
+fn dma_flag_clear(dma_periph: u32, channelx: &DmaChannel, flag: u32) {
+    set_bits(dma_intc(dma_periph), dma_flag_add(flag, *channelx as u32));
+}
+
+fn dma_circulation_enable(dma_periph: u32, channelx: &DmaChannel) -> Result<(), DmaError> {
+    dma_periph_and_channel_check(dma_periph, channelx)?;
     set_bits(dma_chctl(dma_periph, channelx), DMA_CHXCTL_CMEN);
-    Some(())
+    Ok(())
 }
 
-pub fn dma_channel_enable(dma_periph: u32, channelx: &DmaChannel) -> Option<()> {
-    if dma_periph_and_channel_check(dma_periph, channelx).is_none() {
-        return None
-    }
+fn dma_circulation_disable(dma_periph: u32, channelx: &DmaChannel) -> Result<(), DmaError> {
+    dma_periph_and_channel_check(dma_periph, channelx)?;
+    reset_bits(dma_chctl(dma_periph, channelx), DMA_CHXCTL_CMEN);
+    Ok(())
+}
+
+fn dma_channel_enable(dma_periph: u32, channelx: &DmaChannel) -> Result<(), DmaError> {
+    dma_periph_and_channel_check(dma_periph, channelx)?;
     set_bits(dma_chctl(dma_periph, channelx), DMA_CHXCTL_CHEN);
-    Some(())
+    Ok(())
+}
+
+fn dma_channel_disable(dma_periph: u32, channelx: &DmaChannel) -> Result<(), DmaError> {
+    dma_periph_and_channel_check(dma_periph, channelx)?;
+    reset_bits(dma_chctl(dma_periph, channelx), DMA_CHXCTL_CHEN);
+    Ok(())
+}
+
+// In memory-to-memory mode the channel doesn't wait for a peripheral request
+// but transfers as fast as it can, from CHPADDR to CHMADDR or back.
+fn dma_memory_to_memory_enable(dma_periph: u32, channelx: &DmaChannel) -> Result<(), DmaError> {
+    dma_periph_and_channel_check(dma_periph, channelx)?;
+    set_bits(dma_chctl(dma_periph, channelx), DMA_CHXCTL_M2M);
+    Ok(())
+}
+
+// The address and count registers can only be written while the channel is
+// disabled; the new values are used from the next enable on.
+fn dma_transfer_number_config(
+    dma_periph: u32,
+    channelx: &DmaChannel,
+    number: u32,
+) -> Result<(), DmaError> {
+    dma_periph_and_channel_check(dma_periph, channelx)?;
+    if number == 0 || number > DMA_CHANNEL_CNT_MASK {
+        return Err(DmaError::InvalidCount);
+    }
+    set_register(dma_chcnt(dma_periph, channelx), number);
+    Ok(())
+}
+
+// Transfers still to go; counts down while the channel runs and is reloaded
+// when a circular transfer wraps around.
+fn dma_transfer_number_get(dma_periph: u32, channelx: &DmaChannel) -> u32 {
+    read_register(dma_chcnt(dma_periph, channelx)) & DMA_CHANNEL_CNT_MASK
+}
+
+fn dma_interrupt_enable(
+    dma_periph: u32,
+    channelx: &DmaChannel,
+    source: DmaInterrupts,
+) -> Result<(), DmaError> {
+    dma_periph_and_channel_check(dma_periph, channelx)?;
+    set_bits(dma_chctl(dma_periph, channelx), source.bits());
+    Ok(())
+}
+
+// True if `flag` (DMA_INTF_FTFIF, DMA_INTF_HTFIF or DMA_INTF_ERRIF) is set and
+// its interrupt is enabled.
+fn dma_interrupt_flag_get(dma_periph: u32, channelx: &DmaChannel, flag: u32) -> bool {
+    let source = match flag {
+        DMA_INTF_FTFIF => DMA_INT_FTF,
+        DMA_INTF_HTFIF => DMA_INT_HTF,
+        DMA_INTF_ERRIF => DMA_INT_ERR,
+        _ => return false,
+    };
+    let pending = read_register(dma_intf(dma_periph)) & dma_flag_add(flag, *channelx as u32) != 0;
+    let enabled = read_register(dma_chctl(dma_periph, channelx)) & source.bits() != 0;
+    pending && enabled
+}
+
+fn dma_interrupt_flag_clear(dma_periph: u32, channelx: &DmaChannel, flag: u32) {
+    dma_flag_clear(dma_periph, channelx, flag);
+}
+
+/* Controllers and channel tokens */
+// Each controller can be taken once and split into one token per channel, so
+// a channel has a single owner and always refers to a channel that exists.
+// The dma_* functions above are private: a channel can only be programmed
+// through its token.
+
+// Ties a channel token to its controller and channel number.
+pub trait DmaChannelId {
+    const DMA_PERIPH: u32;
+    const CHANNEL: DmaChannel;
+}
+
+macro_rules! dma_controller {
+    ($Dmax:ident, $taken:ident, $periph:expr, $Channels:ident {
+        $($field:ident: $Chx:ident = $channel:ident),*
+    }) => {
+        singleton!($Dmax, $taken, "this controller or its channels");
+
+        impl $Dmax {
+            pub fn split(self) -> $Channels {
+                $Channels {
+                    $($field: $Chx { _private: () }),*
+                }
+            }
+        }
+
+        pub struct $Channels {
+            $(pub $field: $Chx),*
+        }
+
+        $(
+            pub struct $Chx {
+                _private: (),
+            }
+
+            impl DmaChannelId for $Chx {
+                const DMA_PERIPH: u32 = $periph;
+                const CHANNEL: DmaChannel = DmaChannel::$channel;
+            }
+
+            impl $Chx {
+                pub fn init(&mut self, init_struct: &DmaParameters) -> Result<(), DmaError> {
+                    dma_init($periph, &Self::CHANNEL, init_struct)
+                }
+
+                pub fn flag_get(&self, flag: u32) -> bool {
+                    dma_flag_get($periph, &Self::CHANNEL, flag)
+                }
+
+                pub fn flag_clear(&mut self, flag: u32) {
+                    dma_flag_clear($periph, &Self::CHANNEL, flag);
+                }
+
+                pub fn circulation_enable(&mut self) {
+                    set_bits(dma_chctl($periph, &Self::CHANNEL), DMA_CHXCTL_CMEN);
+                }
+
+                pub fn circulation_disable(&mut self) {
+                    reset_bits(dma_chctl($periph, &Self::CHANNEL), DMA_CHXCTL_CMEN);
+                }
+
+                pub fn channel_enable(&mut self) {
+                    set_bits(dma_chctl($periph, &Self::CHANNEL), DMA_CHXCTL_CHEN);
+                }
+
+                pub fn channel_disable(&mut self) {
+                    reset_bits(dma_chctl($periph, &Self::CHANNEL), DMA_CHXCTL_CHEN);
+                }
+
+                pub fn memory_address_config(&mut self, address: u32) {
+                    set_register(dma_chmaddr($periph, &Self::CHANNEL), address);
+                }
+
+                pub fn transfer_number_config(&mut self, number: u32) -> Result<(), DmaError> {
+                    dma_transfer_number_config($periph, &Self::CHANNEL, number)
+                }
+
+                pub fn transfer_number_get(&self) -> u32 {
+                    dma_transfer_number_get($periph, &Self::CHANNEL)
+                }
+
+                pub fn interrupt_enable(&mut self, source: DmaInterrupts) {
+                    set_bits(dma_chctl($periph, &Self::CHANNEL), source.bits());
+                }
+
+                pub fn interrupt_disable(&mut self, source: DmaInterrupts) {
+                    reset_bits(dma_chctl($periph, &Self::CHANNEL), source.bits());
+                }
+
+                pub fn interrupt_flag_get(&self, flag: u32) -> bool {
+                    dma_interrupt_flag_get($periph, &Self::CHANNEL, flag)
+                }
+
+                pub fn interrupt_flag_clear(&mut self, flag: u32) {
+                    dma_interrupt_flag_clear($periph, &Self::CHANNEL, flag);
+                }
+            }
+        )*
+    };
+}
+
+dma_controller!(
+    Dma0,
+    DMA0_TAKEN,
+    DMA0,
+    Dma0Channels {
+        ch0: Dma0Ch0 = DmaCh0,
+        ch1: Dma0Ch1 = DmaCh1,
+        ch2: Dma0Ch2 = DmaCh2,
+        ch3: Dma0Ch3 = DmaCh3,
+        ch4: Dma0Ch4 = DmaCh4,
+        ch5: Dma0Ch5 = DmaCh5,
+        ch6: Dma0Ch6 = DmaCh6
+    }
+);
+
+dma_controller!(
+    Dma1,
+    DMA1_TAKEN,
+    DMA1,
+    Dma1Channels {
+        ch0: Dma1Ch0 = DmaCh0,
+        ch1: Dma1Ch1 = DmaCh1,
+        ch2: Dma1Ch2 = DmaCh2,
+        ch3: Dma1Ch3 = DmaCh3,
+        ch4: Dma1Ch4 = DmaCh4
+    }
+);
+
+/* Peripheral requests */
+// Every peripheral DMA request is wired to one fixed channel. `DmaRequest`
+// names that channel's token type, so code that needs the channel for a
+// request can demand exactly that token, e.g. `<Dac1 as DmaRequest>::Channel`
+// is `Dma1Ch3`. The DAC channels implement it on their driver types (see
+// dac.rs); every other request has a marker type in `request`.
+
+pub trait DmaRequest {
+    type Channel: DmaChannelId;
+}
+
+macro_rules! dma_requests {
+    ($($Chx:ident => $($Request:ident),*;)*) => {
+        pub mod request {
+            $($(pub struct $Request;)*)*
+        }
+
+        $($(
+            impl DmaRequest for request::$Request {
+                type Channel = $Chx;
+            }
+        )*)*
+    };
 }
 
+dma_requests! {
+    Dma0Ch0 => Adc0, Timer1Ch2, Timer3Ch0;
+    Dma0Ch1 => Spi0Rx, Usart2Tx, Timer0Ch0, Timer1Up, Timer2Ch2;
+    Dma0Ch2 => Spi0Tx, Usart2Rx, Timer0Ch1, Timer2Ch3, Timer2Up;
+    Dma0Ch3 => Spi1Rx, I2s1Rx, Usart0Tx, I2c1Tx, Timer0Ch3, Timer0Trg, Timer0Cmt, Timer3Ch1;
+    Dma0Ch4 => Spi1Tx, I2s1Tx, Usart0Rx, I2c1Rx, Timer0Up, Timer1Ch0, Timer3Ch2;
+    Dma0Ch5 => Usart1Rx, I2c0Tx, Timer0Ch2, Timer2Ch0, Timer2Trg;
+    Dma0Ch6 => Usart1Tx, I2c0Rx, Timer1Ch1, Timer1Ch3, Timer3Up;
+    Dma1Ch0 => Spi2Rx, I2s2Rx, Timer4Ch3, Timer4Trg;
+    Dma1Ch1 => Spi2Tx, I2s2Tx, Timer4Ch2, Timer4Up;
+    Dma1Ch2 => Uart3Rx, Timer5Up;
+    Dma1Ch3 => Timer4Ch1, Timer6Up;
+    Dma1Ch4 => Uart3Tx, Timer4Ch0;
+}
+
+/* Memory-to-memory transfers */
+// Copies between two RAM buffers in the background, e.g. to rebuild a waveform
+// table in a back buffer while the CPU does something else:
+//
+//     let mut copy = dma_memcpy(dma0.ch0, &FRONT, back, DmaPriority::Low)?;
+//     ...
+//     copy.wait()?;
+//     let (ch0, _, back) = copy.release();
+
+// Element types DMA can move in one transfer.
+pub trait DmaWord: Copy {
+    const WIDTH: DmaWidth;
+}
+
+impl DmaWord for u8 {
+    const WIDTH: DmaWidth = DmaWidth::Bits8;
+}
+
+impl DmaWord for u16 {
+    const WIDTH: DmaWidth = DmaWidth::Bits16;
+}
+
+impl DmaWord for u32 {
+    const WIDTH: DmaWidth = DmaWidth::Bits32;
+}
+
+pub struct DmaTransfer<C: DmaChannelId, W: 'static> {
+    channel: C,
+    source: &'static [W],
+    destination: &'static mut [W],
+}
+
+// Starts copying `source` into `destination`; both must have the same length.
+pub fn dma_memcpy<C: DmaChannelId, W: DmaWord>(
+    channel: C,
+    source: &'static [W],
+    destination: &'static mut [W],
+    priority: DmaPriority,
+) -> Result<DmaTransfer<C, W>, DmaError> {
+    if source.len() != destination.len() {
+        return Err(DmaError::InvalidCount);
+    }
+    // The channel reads from CHPADDR and writes to CHMADDR.
+    let parameters = DmaParameters {
+        periph_addr: source.as_ptr() as u32,
+        periph_width: W::WIDTH,
+        memory_addr: destination.as_ptr() as u32,
+        memory_width: W::WIDTH,
+        number: source.len() as u32,
+        priority,
+        periph_inc: DmaIncrement::Increase,
+        memory_inc: DmaIncrement::Increase,
+        direction: DmaDirection::PeripheralToMemory,
+    };
+
+    // PADDR, MADDR and CNT are ignored while the channel is still enabled,
+    // e.g. after a previous copy that was never released.
+    dma_channel_disable(C::DMA_PERIPH, &C::CHANNEL)?;
+    dma_flag_clear(C::DMA_PERIPH, &C::CHANNEL, DMA_INTF_GIF);
+    dma_init(C::DMA_PERIPH, &C::CHANNEL, &parameters)?;
+    dma_circulation_disable(C::DMA_PERIPH, &C::CHANNEL)?;
+    dma_memory_to_memory_enable(C::DMA_PERIPH, &C::CHANNEL)?;
+    // The source must be written before DMA starts reading it.
+    compiler_fence(Ordering::Release);
+    dma_channel_enable(C::DMA_PERIPH, &C::CHANNEL)?;
+
+    Ok(DmaTransfer {
+        channel,
+        source,
+        destination,
+    })
+}
+
+impl<C: DmaChannelId, W> DmaTransfer<C, W> {
+    // `None` while the copy is still running.
+    pub fn poll(&mut self) -> Option<Result<(), DmaError>> {
+        if dma_flag_get(C::DMA_PERIPH, &C::CHANNEL, DMA_INTF_ERRIF) {
+            dma_flag_clear(C::DMA_PERIPH, &C::CHANNEL, DMA_INTF_GIF);
+            Some(Err(DmaError::TransferError))
+        } else if dma_flag_get(C::DMA_PERIPH, &C::CHANNEL, DMA_INTF_FTFIF) {
+            dma_flag_clear(C::DMA_PERIPH, &C::CHANNEL, DMA_INTF_GIF);
+            // Keeps reads of the destination after the flag was seen.
+            compiler_fence(Ordering::Acquire);
+            Some(Ok(()))
+        } else {
+            None
+        }
+    }
+
+    // Blocks until the copy has finished.
+    pub fn wait(&mut self) -> Result<(), DmaError> {
+        loop {
+            if let Some(result) = self.poll() {
+                return result;
+            }
+        }
+    }
+
+    // Stops the channel and gives back the channel and both buffers.
+    pub fn release(self) -> (C, &'static [W], &'static mut [W]) {
+        reset_bits(
+            dma_chctl(C::DMA_PERIPH, &C::CHANNEL),
+            DMA_CHXCTL_CHEN | DMA_CHXCTL_M2M,
+        );
+        (self.channel, self.source, self.destination)
+    }
+}
+
+/* Double-buffered streaming */
+// A circular memory-to-peripheral transfer of 16-bit samples whose buffer is
+// split in two halves. While DMA plays one half, the application refills the
+// other from the channel's interrupt handler, giving an endless signal that
+// doesn't have to repeat.
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Half {
+    First,
+    Second,
+}
+
+pub struct DmaStream<C: DmaChannelId> {
+    channel: C,
+    buffer: &'static mut [u16],
+}
+
+impl<C: DmaChannelId> DmaStream<C> {
+    // Starts playing `buffer`, which should already hold the first samples, into
+    // `periph_addr`. `buffer` must have an even length.
+    pub fn start(
+        channel: C,
+        periph_addr: u32,
+        priority: DmaPriority,
+        buffer: &'static mut [u16],
+    ) -> Result<DmaStream<C>, DmaError> {
+        if !buffer.len().is_multiple_of(2) {
+            return Err(DmaError::InvalidCount);
+        }
+        let parameters = DmaParameters {
+            periph_addr,
+            periph_width: DmaWidth::Bits16,
+            memory_addr: buffer.as_ptr() as u32,
+            memory_width: DmaWidth::Bits16,
+            number: buffer.len() as u32,
+            priority,
+            periph_inc: DmaIncrement::Fixed,
+            memory_inc: DmaIncrement::Increase,
+            direction: DmaDirection::MemoryToPeripheral,
+        };
+
+        // The new setup only takes effect on a disabled channel.
+        dma_channel_disable(C::DMA_PERIPH, &C::CHANNEL)?;
+        dma_flag_clear(C::DMA_PERIPH, &C::CHANNEL, DMA_INTF_GIF);
+        dma_init(C::DMA_PERIPH, &C::CHANNEL, &parameters)?;
+        dma_circulation_enable(C::DMA_PERIPH, &C::CHANNEL)?;
+        dma_interrupt_enable(C::DMA_PERIPH, &C::CHANNEL, DMA_INT_HTF | DMA_INT_FTF)?;
+        // The first samples must be in the buffer before DMA reads them.
+        compiler_fence(Ordering::Release);
+        dma_channel_enable(C::DMA_PERIPH, &C::CHANNEL)?;
+
+        Ok(DmaStream { channel, buffer })
+    }
+
+    // Stops the transfer and gives back the channel and the buffer.
+    pub fn stop(self) -> (C, &'static mut [u16]) {
+        reset_bits(
+            dma_chctl(C::DMA_PERIPH, &C::CHANNEL),
+            DMA_CHXCTL_CHEN | (DMA_INT_HTF | DMA_INT_FTF).bits(),
+        );
+        (self.channel, self.buffer)
+    }
+
+    // Call from the channel's interrupt handler. `fill` gets every half DMA has
+    // finished playing since the last call, to write the next samples into.
+    pub fn on_interrupt<F: FnMut(Half, &mut [u16])>(&mut self, mut fill: F) {
+        let middle = self.buffer.len() / 2;
+        let (first, second) = self.buffer.split_at_mut(middle);
+
+        // Each half is only touched after DMA is seen to be done with it, and
+        // handed back once the refill is complete.
+        if dma_interrupt_flag_get(C::DMA_PERIPH, &C::CHANNEL, DMA_INTF_HTFIF) {
+            dma_interrupt_flag_clear(C::DMA_PERIPH, &C::CHANNEL, DMA_INTF_HTFIF);
+            compiler_fence(Ordering::Acquire);
+            fill(Half::First, first);
+            compiler_fence(Ordering::Release);
+        }
+        if dma_interrupt_flag_get(C::DMA_PERIPH, &C::CHANNEL, DMA_INTF_FTFIF) {
+            dma_interrupt_flag_clear(C::DMA_PERIPH, &C::CHANNEL, DMA_INTF_FTFIF);
+            compiler_fence(Ordering::Acquire);
+            fill(Half::Second, second);
+            compiler_fence(Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::sim;
+
+    const DMA0_INTF: u32 = 0x4002_0000;
+    const DMA0_INTC: u32 = 0x4002_0004;
+    const DMA1_INTF: u32 = 0x4002_0400;
+    const DMA1_INTC: u32 = 0x4002_0404;
+    const DMA1_CH2CTL: u32 = 0x4002_0430;
+
+    fn buffer() -> &'static mut [u16] {
+        std::boxed::Box::leak(std::vec![0u16; 8].into_boxed_slice())
+    }
+
+    fn parameters(periph_addr: u32, memory_addr: u32) -> DmaParameters {
+        DmaParameters {
+            periph_addr,
+            periph_width: DmaWidth::Bits32,
+            memory_addr,
+            memory_width: DmaWidth::Bits16,
+            number: 4,
+            priority: DmaPriority::Low,
+            periph_inc: DmaIncrement::Fixed,
+            memory_inc: DmaIncrement::Increase,
+            direction: DmaDirection::MemoryToPeripheral,
+        }
+    }
+
+    #[test]
+    fn init_validates_before_writing() {
+        sim::reset();
+        let mut channels = unsafe { Dma0::steal() }.split();
+
+        assert_eq!(
+            channels.ch6.init(&parameters(0x4000_7402, 0x2000_0000)),
+            Err(DmaError::UnalignedPeriphAddress)
+        );
+        assert_eq!(
+            channels.ch6.init(&parameters(0x4000_7400, 0x2000_0001)),
+            Err(DmaError::UnalignedMemoryAddress)
+        );
+        let mut too_long = parameters(0x4000_7400, 0x2000_0000);
+        too_long.number = 0x1_0000;
+        assert_eq!(channels.ch6.init(&too_long), Err(DmaError::InvalidCount));
+        assert_eq!(sim::take_writes(), []);
+
+        assert_eq!(
+            channels.ch6.init(&parameters(0x4000_7400, 0x2000_0002)),
+            Ok(())
+        );
+        // DMA0 CH6CTL: 16-bit memory, 32-bit peripheral, memory increment,
+        // to peripheral
+        assert_eq!(sim::peek(0x4002_0080), 0x690);
+    }
+
+    #[test]
+    fn channel_check() {
+        assert_eq!(
+            dma_periph_and_channel_check(DMA0, &DmaChannel::DmaCh6),
+            Ok(())
+        );
+        assert_eq!(
+            dma_periph_and_channel_check(DMA1, &DmaChannel::DmaCh4),
+            Ok(())
+        );
+        assert_eq!(
+            dma_periph_and_channel_check(DMA1, &DmaChannel::DmaCh5),
+            Err(DmaError::InvalidChannel)
+        );
+        assert_eq!(
+            dma_periph_and_channel_check(0x4002_0800, &DmaChannel::DmaCh0),
+            Err(DmaError::InvalidPeriph)
+        );
+    }
+
+    fn channel_of<R: DmaRequest>() -> (u32, DmaChannel) {
+        (R::Channel::DMA_PERIPH, R::Channel::CHANNEL)
+    }
+
+    #[test]
+    fn requests_map_to_their_channels() {
+        use crate::dac::{Dac0, Dac1};
+
+        assert_eq!(channel_of::<Dac0>(), (DMA1, DmaChannel::DmaCh2));
+        assert_eq!(channel_of::<Dac1>(), (DMA1, DmaChannel::DmaCh3));
+        assert_eq!(
+            channel_of::<request::Timer5Up>(),
+            (DMA1, DmaChannel::DmaCh2)
+        );
+        assert_eq!(channel_of::<request::Adc0>(), (DMA0, DmaChannel::DmaCh0));
+        assert_eq!(
+            channel_of::<request::Usart0Tx>(),
+            (DMA0, DmaChannel::DmaCh3)
+        );
+        assert_eq!(
+            channel_of::<request::Usart0Rx>(),
+            (DMA0, DmaChannel::DmaCh4)
+        );
+        assert_eq!(channel_of::<request::Spi0Rx>(), (DMA0, DmaChannel::DmaCh1));
+        assert_eq!(channel_of::<request::I2c0Tx>(), (DMA0, DmaChannel::DmaCh5));
+        assert_eq!(
+            channel_of::<request::Timer3Up>(),
+            (DMA0, DmaChannel::DmaCh6)
+        );
+        assert_eq!(channel_of::<request::Spi2Tx>(), (DMA1, DmaChannel::DmaCh1));
+        assert_eq!(channel_of::<request::Uart3Tx>(), (DMA1, DmaChannel::DmaCh4));
+    }
+
+    #[test]
+    fn stop_and_reprogram_a_channel() {
+        sim::reset();
+        let mut channels = unsafe { Dma1::steal() }.split();
+        let ch3 = &mut channels.ch3;
+        // DMA1 CH3CTL, CH3CNT, CH3MADDR
+        sim::preset(0x4002_0444, 0x25b1);
+        sim::preset(0x4002_0448, 0x0001_0007);
+
+        ch3.channel_disable();
+        ch3.circulation_disable();
+        ch3.memory_address_config(0x2000_0100);
+        assert_eq!(ch3.transfer_number_config(0), Err(DmaError::InvalidCount));
+        assert_eq!(ch3.transfer_number_config(64), Ok(()));
+
+        assert_eq!(sim::peek(0x4002_0444), 0x2590);
+        assert_eq!(sim::peek(0x4002_0450), 0x2000_0100);
+        assert_eq!(ch3.transfer_number_get(), 64);
+    }
+
+    #[test]
+    fn error_flag_and_interrupt() {
+        sim::reset();
+        let mut channels = unsafe { Dma1::steal() }.split();
+        let ch4 = &mut channels.ch4;
+        sim::preset(DMA1_INTF, (DMA_INTF_GIF | DMA_INTF_ERRIF) << 16);
+
+        assert!(ch4.flag_get(DMA_INTF_ERRIF));
+        assert!(!ch4.flag_get(DMA_INTF_FTFIF));
+        assert!(!ch4.interrupt_flag_get(DMA_INTF_ERRIF));
+
+        ch4.interrupt_enable(DMA_INT_ERR);
+        assert!(ch4.interrupt_flag_get(DMA_INTF_ERRIF));
+        // DMA1 CH4CTL: ERRIE
+        assert_eq!(sim::peek(0x4002_0458), 0x8);
+
+        ch4.interrupt_enable(DMA_INT_HTF | DMA_INT_FTF);
+        ch4.interrupt_disable(DMA_INT_ERR);
+        assert_eq!(sim::peek(0x4002_0458), 0x6);
+    }
+
+    #[test]
+    fn memcpy_programs_memory_to_memory() {
+        static SOURCE: [u32; 4] = [1, 2, 3, 4];
+        sim::reset();
+        let channels = unsafe { Dma0::steal() }.split();
+        let destination = std::boxed::Box::leak(std::boxed::Box::new([0u32; 4]));
+        // Still enabled from an earlier transfer
+        sim::preset(0x4002_001c, 0x1);
+
+        let mut copy = dma_memcpy(channels.ch1, &SOURCE, destination, DmaPriority::Medium).unwrap();
+
+        // Disabled before the first address is written
+        let writes = sim::take_writes();
+        assert_eq!(writes[0], (0x4002_001c, 0x0));
+
+        // DMA0 CH1PADDR, CH1CNT, CH1CTL
+        assert_eq!(sim::peek(0x4002_0024), SOURCE.as_ptr() as u32);
+        assert_eq!(sim::peek(0x4002_0020), 4);
+        // M2M, medium priority, 32-bit widths, both addresses increase, enabled
+        assert_eq!(sim::peek(0x4002_001c), 0x5ac1);
+
+        assert_eq!(copy.poll(), None);
+        sim::preset(DMA0_INTF, (DMA_INTF_GIF | DMA_INTF_FTFIF) << 4);
+        assert_eq!(copy.wait(), Ok(()));
+        assert_eq!(sim::peek(DMA0_INTC), DMA_INTF_GIF << 4);
+
+        let (_, _, destination) = copy.release();
+        assert_eq!(destination.len(), 4);
+        assert_eq!(sim::peek(0x4002_001c), 0x1ac0);
+    }
+
+    #[test]
+    fn memcpy_reports_errors() {
+        static SOURCE: [u8; 3] = [1, 2, 3];
+        sim::reset();
+        let mut channels = unsafe { Dma0::steal() }.split();
+        let short = std::boxed::Box::leak(std::boxed::Box::new([0u8; 2]));
+
+        assert_eq!(
+            dma_memcpy(channels.ch2, &SOURCE, short, DmaPriority::Low).err(),
+            Some(DmaError::InvalidCount)
+        );
+
+        let destination = std::boxed::Box::leak(std::boxed::Box::new([0u8; 3]));
+        channels = unsafe { Dma0::steal() }.split();
+        let mut copy = dma_memcpy(channels.ch2, &SOURCE, destination, DmaPriority::Low).unwrap();
+        sim::preset(DMA0_INTF, (DMA_INTF_GIF | DMA_INTF_ERRIF) << 8);
+        assert_eq!(copy.wait(), Err(DmaError::TransferError));
+    }
+
+    #[test]
+    fn stream_rejects_odd_buffers() {
+        sim::reset();
+        let odd = &mut buffer()[..7];
+
+        let channels = unsafe { Dma1::steal() }.split();
+
+        assert_eq!(
+            DmaStream::start(channels.ch2, 0, DmaPriority::High, odd).err(),
+            Some(DmaError::InvalidCount)
+        );
+    }
+
+    #[test]
+    fn stream_hands_out_finished_half() {
+        sim::reset();
+        let channels = unsafe { Dma1::steal() }.split();
+        // Still enabled from an earlier transfer
+        sim::preset(DMA1_CH2CTL, 0x1);
+        let mut stream =
+            DmaStream::start(channels.ch2, 0x4000_7408, DmaPriority::High, buffer()).unwrap();
+        // Disabled before the first address is written
+        assert_eq!(sim::take_writes()[0], (DMA1_CH2CTL, 0x0));
+        // 16-bit, high priority, memory increment, to peripheral, circular,
+        // half and full transfer interrupts, enabled
+        assert_eq!(sim::peek(DMA1_CH2CTL), 0x25b7);
+
+        sim::preset(DMA1_INTC, 0);
+        sim::preset(DMA1_INTF, DMA_INTF_HTFIF << 8);
+        let mut halves = std::vec::Vec::new();
+        stream.on_interrupt(|half, samples| {
+            samples.copy_from_slice(&[1, 2, 3, 4]);
+            halves.push(half);
+        });
+
+        assert_eq!(halves, [Half::First]);
+        assert_eq!(sim::peek(DMA1_INTC), DMA_INTF_HTFIF << 8);
+        assert_eq!(stream.buffer, [1, 2, 3, 4, 0, 0, 0, 0]);
+
+        sim::preset(DMA1_INTF, DMA_INTF_FTFIF << 8);
+        stream.on_interrupt(|half, samples| {
+            samples.copy_from_slice(&[5, 6, 7, 8]);
+            halves.push(half);
+        });
+
+        assert_eq!(halves, [Half::First, Half::Second]);
+        assert_eq!(stream.buffer, [1, 2, 3, 4, 5, 6, 7, 8]);
+
+        let (_, buffer) = stream.stop();
+        assert_eq!(buffer.len(), 8);
+        assert_eq!(sim::peek(DMA1_CH2CTL), 0x25b0);
+    }
+}
@@ -0,0 +1,471 @@
+use crate::csr::*;
+use crate::register_helpers::*;
+
+/* ECLIC definitions */
+pub const ECLIC: u32 = 0xD200_0000;
+
+const ECLIC_CLICCFG: u32 = ECLIC;
+const ECLIC_MTH: u32 = ECLIC + 0xB;
+
+const fn eclic_int_ip(source: u32) -> u32 {
+    ECLIC + 0x1000 + 4 * source
+}
+
+const fn eclic_int_ie(source: u32) -> u32 {
+    eclic_int_ip(source) + 1
+}
+
+const fn eclic_int_attr(source: u32) -> u32 {
+    eclic_int_ip(source) + 2
+}
+
+const fn eclic_int_ctl(source: u32) -> u32 {
+    eclic_int_ip(source) + 3
+}
+
+/* ECLIC bits */
+const ECLIC_CLICCFG_NLBITS: u8 = 0x1e;
+const ECLIC_INT_IP: u8 = 0x01;
+const ECLIC_INT_IE: u8 = 0x01;
+const ECLIC_INT_ATTR_TRIG: u8 = 0x06;
+
+// Only the upper CLICINTCTLBITS bits of the control registers are implemented.
+const ECLIC_CLICINTCTLBITS: u8 = 4;
+
+pub const ECLIC_NUM_INTERRUPTS: usize = 87;
+
+#[repr(u32)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Interrupt {
+    ClicIntSft = 3,
+    ClicIntTmr = 7,
+    ClicIntBwei = 17,
+    ClicIntPmovi = 18,
+    Wwdgt = 19,
+    Lvd = 20,
+    Tamper = 21,
+    Rtc = 22,
+    Fmc = 23,
+    Rcu = 24,
+    Exti0 = 25,
+    Exti1 = 26,
+    Exti2 = 27,
+    Exti3 = 28,
+    Exti4 = 29,
+    Dma0Channel0 = 30,
+    Dma0Channel1 = 31,
+    Dma0Channel2 = 32,
+    Dma0Channel3 = 33,
+    Dma0Channel4 = 34,
+    Dma0Channel5 = 35,
+    Dma0Channel6 = 36,
+    Adc0Adc1 = 37,
+    Can0Tx = 38,
+    Can0Rx0 = 39,
+    Can0Rx1 = 40,
+    Can0Ewmc = 41,
+    Exti5To9 = 42,
+    Timer0Brk = 43,
+    Timer0Up = 44,
+    Timer0TrgCmt = 45,
+    Timer0Channel = 46,
+    Timer1 = 47,
+    Timer2 = 48,
+    Timer3 = 49,
+    I2c0Ev = 50,
+    I2c0Er = 51,
+    I2c1Ev = 52,
+    I2c1Er = 53,
+    Spi0 = 54,
+    Spi1 = 55,
+    Usart0 = 56,
+    Usart1 = 57,
+    Usart2 = 58,
+    Exti10To15 = 59,
+    RtcAlarm = 60,
+    UsbfsWkup = 61,
+    Exmc = 67,
+    Timer4 = 69,
+    Spi2 = 70,
+    Uart3 = 71,
+    Uart4 = 72,
+    Timer5 = 73,
+    Timer6 = 74,
+    Dma1Channel0 = 75,
+    Dma1Channel1 = 76,
+    Dma1Channel2 = 77,
+    Dma1Channel3 = 78,
+    Dma1Channel4 = 79,
+    Can1Tx = 82,
+    Can1Rx0 = 83,
+    Can1Rx1 = 84,
+    Can1Ewmc = 85,
+    Usbfs = 86,
+}
+
+// How the CLICINTCTLBITS control bits are split between preemption level and
+// priority; e.g. `Level2Prio2` gives 4 levels with 4 priorities each.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PriorityGroup {
+    Level0Prio4 = 0,
+    Level1Prio3 = 1,
+    Level2Prio2 = 2,
+    Level3Prio1 = 3,
+    Level4Prio0 = 4,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Trigger {
+    Level = 0x0,
+    RisingEdge = 0x2,
+    FallingEdge = 0x6,
+}
+
+// Puts the ECLIC in its reset state: every line disabled, not pending, level
+// triggered, with all control bits cleared.
+pub fn eclic_init() {
+    set_register8(reg8(ECLIC_CLICCFG), 0);
+    set_register8(reg8(ECLIC_MTH), 0);
+    for source in 0..ECLIC_NUM_INTERRUPTS as u32 {
+        set_register8(reg8(eclic_int_ip(source)), 0);
+        set_register8(reg8(eclic_int_ie(source)), 0);
+        set_register8(reg8(eclic_int_attr(source)), 0);
+        set_register8(reg8(eclic_int_ctl(source)), 0);
+    }
+}
+
+pub fn eclic_priority_group_set(prigroup: PriorityGroup) {
+    let cliccfg = read_register8(reg8(ECLIC_CLICCFG));
+    let nlbits = ((prigroup as u8) << 1) & ECLIC_CLICCFG_NLBITS;
+    set_register8(
+        reg8(ECLIC_CLICCFG),
+        (cliccfg & !ECLIC_CLICCFG_NLBITS) | nlbits,
+    );
+}
+
+pub fn eclic_global_interrupt_enable() {
+    mstatus_set(MSTATUS_MIE);
+}
+
+pub fn eclic_global_interrupt_disable() {
+    mstatus_clear(MSTATUS_MIE);
+}
+
+// `level` and `priority` must fit the bits the current priority group gives
+// them.
+pub fn eclic_irq_enable(source: Interrupt, level: u8, priority: u8) -> Option<()> {
+    let source = source as u32;
+    let nlbits = (read_register8(reg8(ECLIC_CLICCFG)) & ECLIC_CLICCFG_NLBITS) >> 1;
+    let nlbits = nlbits.min(ECLIC_CLICINTCTLBITS);
+    let prio_bits = ECLIC_CLICINTCTLBITS - nlbits;
+    if level as u32 >= 1 << nlbits || priority as u32 >= 1 << prio_bits {
+        return None;
+    }
+
+    // Level in the topmost bits, priority right below, unimplemented bits as 1.
+    let level = if nlbits == 0 {
+        0
+    } else {
+        level << (8 - nlbits)
+    };
+    let priority = if prio_bits == 0 {
+        0
+    } else {
+        priority << (8 - ECLIC_CLICINTCTLBITS)
+    };
+    let ctl = level | priority | (0xff >> ECLIC_CLICINTCTLBITS);
+    set_register8(reg8(eclic_int_ctl(source)), ctl);
+    set_register8(reg8(eclic_int_ie(source)), ECLIC_INT_IE);
+    Some(())
+}
+
+pub fn eclic_irq_disable(source: Interrupt) {
+    set_register8(reg8(eclic_int_ie(source as u32)), 0);
+}
+
+pub fn eclic_set_trigger(source: Interrupt, trigger: Trigger) {
+    let attr = reg8(eclic_int_attr(source as u32));
+    let value = read_register8(attr) & !ECLIC_INT_ATTR_TRIG;
+    set_register8(attr, value | trigger as u8);
+}
+
+// Interrupts at or below `level` are masked.
+pub fn eclic_set_threshold(level: u8) {
+    set_register8(reg8(ECLIC_MTH), level);
+}
+
+pub fn eclic_pending_set(source: Interrupt) {
+    set_register8(reg8(eclic_int_ip(source as u32)), ECLIC_INT_IP);
+}
+
+pub fn eclic_pending_clear(source: Interrupt) {
+    set_register8(reg8(eclic_int_ip(source as u32)), 0);
+}
+
+pub fn eclic_pending_get(source: Interrupt) -> bool {
+    read_register8(reg8(eclic_int_ip(source as u32))) & ECLIC_INT_IP != 0
+}
+
+/* Vector table */
+// Interrupts are handled in non-vectored mode: every trap enters through
+// `_start_trap` (see trap.rs), which calls `eclic_dispatch` for interrupts.
+// Handlers are plain `extern "C"` functions; override one by defining it by
+// name, e.g.
+//
+//     #[no_mangle]
+//     extern "C" fn TIMER5_IRQHandler() { ... }
+//
+// Anything not overridden ends up in `DefaultHandler` (see link.x).
+
+// Points mtvec at `_start_trap`, with the mode bits selecting ECLIC mode.
+#[cfg(target_arch = "riscv32")]
+pub fn eclic_mode_enable() {
+    extern "C" {
+        fn _start_trap();
+    }
+    mtvec_write(_start_trap as usize as u32 | 0x3);
+}
+
+#[cfg(target_arch = "riscv32")]
+pub(crate) fn eclic_dispatch(source: u16) {
+    match __INTERRUPTS.get(source as usize) {
+        Some(Some(handler)) => unsafe { handler() },
+        _ => unsafe { DefaultHandler() },
+    }
+}
+
+#[cfg(target_arch = "riscv32")]
+#[no_mangle]
+extern "C" fn DefaultHandler_() {
+    loop {}
+}
+
+#[cfg(target_arch = "riscv32")]
+extern "C" {
+    fn DefaultHandler();
+
+    fn eclic_msip_handler();
+    fn eclic_mtip_handler();
+    fn eclic_bwei_handler();
+    fn eclic_pmovi_handler();
+    fn WWDGT_IRQHandler();
+    fn LVD_IRQHandler();
+    fn TAMPER_IRQHandler();
+    fn RTC_IRQHandler();
+    fn FMC_IRQHandler();
+    fn RCU_IRQHandler();
+    fn EXTI0_IRQHandler();
+    fn EXTI1_IRQHandler();
+    fn EXTI2_IRQHandler();
+    fn EXTI3_IRQHandler();
+    fn EXTI4_IRQHandler();
+    fn DMA0_Channel0_IRQHandler();
+    fn DMA0_Channel1_IRQHandler();
+    fn DMA0_Channel2_IRQHandler();
+    fn DMA0_Channel3_IRQHandler();
+    fn DMA0_Channel4_IRQHandler();
+    fn DMA0_Channel5_IRQHandler();
+    fn DMA0_Channel6_IRQHandler();
+    fn ADC0_1_IRQHandler();
+    fn CAN0_TX_IRQHandler();
+    fn CAN0_RX0_IRQHandler();
+    fn CAN0_RX1_IRQHandler();
+    fn CAN0_EWMC_IRQHandler();
+    fn EXTI5_9_IRQHandler();
+    fn TIMER0_BRK_IRQHandler();
+    fn TIMER0_UP_IRQHandler();
+    fn TIMER0_TRG_CMT_IRQHandler();
+    fn TIMER0_Channel_IRQHandler();
+    fn TIMER1_IRQHandler();
+    fn TIMER2_IRQHandler();
+    fn TIMER3_IRQHandler();
+    fn I2C0_EV_IRQHandler();
+    fn I2C0_ER_IRQHandler();
+    fn I2C1_EV_IRQHandler();
+    fn I2C1_ER_IRQHandler();
+    fn SPI0_IRQHandler();
+    fn SPI1_IRQHandler();
+    fn USART0_IRQHandler();
+    fn USART1_IRQHandler();
+    fn USART2_IRQHandler();
+    fn EXTI10_15_IRQHandler();
+    fn RTC_Alarm_IRQHandler();
+    fn USBFS_WKUP_IRQHandler();
+    fn EXMC_IRQHandler();
+    fn TIMER4_IRQHandler();
+    fn SPI2_IRQHandler();
+    fn UART3_IRQHandler();
+    fn UART4_IRQHandler();
+    fn TIMER5_IRQHandler();
+    fn TIMER6_IRQHandler();
+    fn DMA1_Channel0_IRQHandler();
+    fn DMA1_Channel1_IRQHandler();
+    fn DMA1_Channel2_IRQHandler();
+    fn DMA1_Channel3_IRQHandler();
+    fn DMA1_Channel4_IRQHandler();
+    fn CAN1_TX_IRQHandler();
+    fn CAN1_RX0_IRQHandler();
+    fn CAN1_RX1_IRQHandler();
+    fn CAN1_EWMC_IRQHandler();
+    fn USBFS_IRQHandler();
+}
+
+// Indexed by interrupt id; `None` for the ids the chip doesn't use.
+#[cfg(target_arch = "riscv32")]
+#[no_mangle]
+pub static __INTERRUPTS: [Option<unsafe extern "C" fn()>; ECLIC_NUM_INTERRUPTS] = [
+    None,
+    None,
+    None,
+    Some(eclic_msip_handler),
+    None,
+    None,
+    None,
+    Some(eclic_mtip_handler),
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    None,
+    Some(eclic_bwei_handler),
+    Some(eclic_pmovi_handler),
+    Some(WWDGT_IRQHandler),
+    Some(LVD_IRQHandler),
+    Some(TAMPER_IRQHandler),
+    Some(RTC_IRQHandler),
+    Some(FMC_IRQHandler),
+    Some(RCU_IRQHandler),
+    Some(EXTI0_IRQHandler),
+    Some(EXTI1_IRQHandler),
+    Some(EXTI2_IRQHandler),
+    Some(EXTI3_IRQHandler),
+    Some(EXTI4_IRQHandler),
+    Some(DMA0_Channel0_IRQHandler),
+    Some(DMA0_Channel1_IRQHandler),
+    Some(DMA0_Channel2_IRQHandler),
+    Some(DMA0_Channel3_IRQHandler),
+    Some(DMA0_Channel4_IRQHandler),
+    Some(DMA0_Channel5_IRQHandler),
+    Some(DMA0_Channel6_IRQHandler),
+    Some(ADC0_1_IRQHandler),
+    Some(CAN0_TX_IRQHandler),
+    Some(CAN0_RX0_IRQHandler),
+    Some(CAN0_RX1_IRQHandler),
+    Some(CAN0_EWMC_IRQHandler),
+    Some(EXTI5_9_IRQHandler),
+    Some(TIMER0_BRK_IRQHandler),
+    Some(TIMER0_UP_IRQHandler),
+    Some(TIMER0_TRG_CMT_IRQHandler),
+    Some(TIMER0_Channel_IRQHandler),
+    Some(TIMER1_IRQHandler),
+    Some(TIMER2_IRQHandler),
+    Some(TIMER3_IRQHandler),
+    Some(I2C0_EV_IRQHandler),
+    Some(I2C0_ER_IRQHandler),
+    Some(I2C1_EV_IRQHandler),
+    Some(I2C1_ER_IRQHandler),
+    Some(SPI0_IRQHandler),
+    Some(SPI1_IRQHandler),
+    Some(USART0_IRQHandler),
+    Some(USART1_IRQHandler),
+    Some(USART2_IRQHandler),
+    Some(EXTI10_15_IRQHandler),
+    Some(RTC_Alarm_IRQHandler),
+    Some(USBFS_WKUP_IRQHandler),
+    None,
+    None,
+    None,
+    None,
+    None,
+    Some(EXMC_IRQHandler),
+    None,
+    Some(TIMER4_IRQHandler),
+    Some(SPI2_IRQHandler),
+    Some(UART3_IRQHandler),
+    Some(UART4_IRQHandler),
+    Some(TIMER5_IRQHandler),
+    Some(TIMER6_IRQHandler),
+    Some(DMA1_Channel0_IRQHandler),
+    Some(DMA1_Channel1_IRQHandler),
+    Some(DMA1_Channel2_IRQHandler),
+    Some(DMA1_Channel3_IRQHandler),
+    Some(DMA1_Channel4_IRQHandler),
+    None,
+    None,
+    Some(CAN1_TX_IRQHandler),
+    Some(CAN1_RX0_IRQHandler),
+    Some(CAN1_RX1_IRQHandler),
+    Some(CAN1_EWMC_IRQHandler),
+    Some(USBFS_IRQHandler),
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::sim::{self, Access};
+
+    #[test]
+    fn register_addresses() {
+        assert_eq!(eclic_int_ip(Interrupt::Timer5 as u32), 0xD200_1124);
+        assert_eq!(eclic_int_ie(Interrupt::Timer5 as u32), 0xD200_1125);
+        assert_eq!(eclic_int_attr(Interrupt::Timer5 as u32), 0xD200_1126);
+        assert_eq!(eclic_int_ctl(Interrupt::Timer5 as u32), 0xD200_1127);
+    }
+
+    #[test]
+    fn irq_enable_packs_level_and_priority() {
+        sim::reset();
+        eclic_priority_group_set(PriorityGroup::Level3Prio1);
+        sim::take_log();
+
+        assert_eq!(eclic_irq_enable(Interrupt::Dma1Channel2, 5, 1), Some(()));
+
+        let ctl = eclic_int_ctl(Interrupt::Dma1Channel2 as u32);
+        let ie = eclic_int_ie(Interrupt::Dma1Channel2 as u32);
+        assert_eq!(
+            sim::take_log(),
+            [
+                Access::Read8(ECLIC_CLICCFG, 0x06),
+                Access::Write8(ctl, 0b1011_1111),
+                Access::Write8(ie, 0x01),
+            ]
+        );
+    }
+
+    #[test]
+    fn irq_enable_rejects_out_of_range() {
+        sim::reset();
+        eclic_priority_group_set(PriorityGroup::Level2Prio2);
+        sim::take_log();
+
+        assert_eq!(eclic_irq_enable(Interrupt::Timer5, 4, 0), None);
+        assert_eq!(eclic_irq_enable(Interrupt::Timer5, 0, 4), None);
+        assert_eq!(sim::take_writes(), []);
+    }
+
+    #[test]
+    fn trigger_keeps_other_attr_bits() {
+        sim::reset();
+        let attr = eclic_int_attr(Interrupt::Exti0 as u32);
+        sim::preset(attr & !0x3, 0x01 << 16);
+
+        eclic_set_trigger(Interrupt::Exti0, Trigger::FallingEdge);
+        assert_eq!(read_register8(reg8(attr)), 0x07);
+        eclic_set_trigger(Interrupt::Exti0, Trigger::RisingEdge);
+        assert_eq!(read_register8(reg8(attr)), 0x03);
+    }
+
+    #[test]
+    fn pending_set_and_clear() {
+        sim::reset();
+
+        eclic_pending_set(Interrupt::Timer6);
+        assert!(eclic_pending_get(Interrupt::Timer6));
+        eclic_pending_clear(Interrupt::Timer6);
+        assert!(!eclic_pending_get(Interrupt::Timer6));
+    }
+}
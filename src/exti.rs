@@ -0,0 +1,175 @@
+use crate::register_helpers::*;
+
+/* EXTI constants */
+const APB2_BUS_BASE: u32 = 0x4001_0000;
+const EXTI: u32 = APB2_BUS_BASE + 0x0000_0400;
+
+const EXTI_INTEN: Reg32 = reg32(EXTI + 0x00); // interrupt enable
+const EXTI_EVEN: Reg32 = reg32(EXTI + 0x04); // event enable
+const EXTI_RTEN: Reg32 = reg32(EXTI + 0x08); // rising edge trigger enable
+const EXTI_FTEN: Reg32 = reg32(EXTI + 0x0C); // falling edge trigger enable
+const EXTI_SWIEV: Reg32 = reg32(EXTI + 0x10); // software interrupt event
+const EXTI_PD: Reg32 = reg32(EXTI + 0x14); // pending
+
+// Lines 0..=15 are the GPIO pins selected with `gpio_exti_source_select`;
+// the others are wired to internal sources.
+pub const EXTI_0: u32 = bit(0);
+pub const EXTI_1: u32 = bit(1);
+pub const EXTI_2: u32 = bit(2);
+pub const EXTI_3: u32 = bit(3);
+pub const EXTI_4: u32 = bit(4);
+pub const EXTI_5: u32 = bit(5);
+pub const EXTI_6: u32 = bit(6);
+pub const EXTI_7: u32 = bit(7);
+pub const EXTI_8: u32 = bit(8);
+pub const EXTI_9: u32 = bit(9);
+pub const EXTI_10: u32 = bit(10);
+pub const EXTI_11: u32 = bit(11);
+pub const EXTI_12: u32 = bit(12);
+pub const EXTI_13: u32 = bit(13);
+pub const EXTI_14: u32 = bit(14);
+pub const EXTI_15: u32 = bit(15);
+pub const EXTI_16: u32 = bit(16); // LVD
+pub const EXTI_17: u32 = bit(17); // RTC alarm
+pub const EXTI_18: u32 = bit(18); // USBFS wakeup
+
+const EXTI_LINES: u32 = bits(0, 18);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExtiMode {
+    // Sets the pending flag and raises the line's interrupt.
+    Interrupt,
+    // Only generates an event pulse, e.g. to trigger the DAC or wake the core.
+    Event,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ExtiTrigger {
+    Rising,
+    Falling,
+    Both,
+    None,
+}
+
+pub fn exti_deinit() {
+    set_register(EXTI_INTEN, 0);
+    set_register(EXTI_EVEN, 0);
+    set_register(EXTI_RTEN, 0);
+    set_register(EXTI_FTEN, 0);
+    set_register(EXTI_SWIEV, 0);
+    set_register(EXTI_PD, EXTI_LINES);
+}
+
+// Enables `linex` in `mode` only, and fires it on the given edges.
+pub fn exti_init(linex: u32, mode: ExtiMode, trig_type: ExtiTrigger) {
+    reset_bits(EXTI_INTEN, linex);
+    reset_bits(EXTI_EVEN, linex);
+    reset_bits(EXTI_RTEN, linex);
+    reset_bits(EXTI_FTEN, linex);
+
+    match mode {
+        ExtiMode::Interrupt => set_bits(EXTI_INTEN, linex),
+        ExtiMode::Event => set_bits(EXTI_EVEN, linex),
+    }
+
+    match trig_type {
+        ExtiTrigger::Rising => set_bits(EXTI_RTEN, linex),
+        ExtiTrigger::Falling => set_bits(EXTI_FTEN, linex),
+        ExtiTrigger::Both => {
+            set_bits(EXTI_RTEN, linex);
+            set_bits(EXTI_FTEN, linex);
+        }
+        ExtiTrigger::None => (),
+    }
+}
+
+pub fn exti_interrupt_enable(linex: u32) {
+    set_bits(EXTI_INTEN, linex);
+}
+
+pub fn exti_interrupt_disable(linex: u32) {
+    reset_bits(EXTI_INTEN, linex);
+}
+
+pub fn exti_event_enable(linex: u32) {
+    set_bits(EXTI_EVEN, linex);
+}
+
+pub fn exti_event_disable(linex: u32) {
+    reset_bits(EXTI_EVEN, linex);
+}
+
+// Fires the line as if its edge had been detected.
+pub fn exti_software_interrupt_enable(linex: u32) {
+    set_bits(EXTI_SWIEV, linex);
+}
+
+pub fn exti_software_interrupt_disable(linex: u32) {
+    reset_bits(EXTI_SWIEV, linex);
+}
+
+pub fn exti_flag_get(linex: u32) -> bool {
+    read_register(EXTI_PD) & linex != 0
+}
+
+// PD bits are cleared by writing 1; writing 0 leaves the others alone.
+pub fn exti_flag_clear(linex: u32) {
+    set_register(EXTI_PD, linex);
+}
+
+// True if `linex` is pending and its interrupt is enabled.
+pub fn exti_interrupt_flag_get(linex: u32) -> bool {
+    exti_flag_get(linex) && read_register(EXTI_INTEN) & linex != 0
+}
+
+pub fn exti_interrupt_flag_clear(linex: u32) {
+    exti_flag_clear(linex);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::sim;
+
+    const INTEN: u32 = 0x4001_0400;
+    const EVEN: u32 = 0x4001_0404;
+    const RTEN: u32 = 0x4001_0408;
+    const FTEN: u32 = 0x4001_040c;
+    const PD: u32 = 0x4001_0414;
+
+    #[test]
+    fn init_switches_mode_and_edges() {
+        sim::reset();
+        sim::preset(INTEN, EXTI_9 | EXTI_0);
+        sim::preset(RTEN, EXTI_9);
+
+        exti_init(EXTI_9, ExtiMode::Event, ExtiTrigger::Falling);
+
+        assert_eq!(sim::peek(INTEN), EXTI_0);
+        assert_eq!(sim::peek(EVEN), EXTI_9);
+        assert_eq!(sim::peek(RTEN), 0);
+        assert_eq!(sim::peek(FTEN), EXTI_9);
+
+        exti_init(EXTI_9, ExtiMode::Interrupt, ExtiTrigger::Both);
+
+        assert_eq!(sim::peek(INTEN), EXTI_0 | EXTI_9);
+        assert_eq!(sim::peek(EVEN), 0);
+        assert_eq!(sim::peek(RTEN), EXTI_9);
+        assert_eq!(sim::peek(FTEN), EXTI_9);
+    }
+
+    #[test]
+    fn pending_flags_clear_by_writing_one() {
+        sim::reset();
+        sim::preset(PD, EXTI_9 | EXTI_3);
+
+        assert!(exti_flag_get(EXTI_9));
+        assert!(!exti_interrupt_flag_get(EXTI_9));
+        exti_interrupt_enable(EXTI_9);
+        assert!(exti_interrupt_flag_get(EXTI_9));
+
+        sim::take_log();
+        exti_interrupt_flag_clear(EXTI_9);
+        assert_eq!(sim::take_writes(), [(PD, EXTI_9)]);
+    }
+}
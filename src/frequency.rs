@@ -0,0 +1,224 @@
+use crate::timer::*;
+
+/* Output frequency planning */
+// A table of `table_len` samples played through DMA, one sample per timer
+// update, repeats at timer_clock / ((prescaler + 1) * (autoreload + 1) * table_len).
+// The planner picks the prescaler/autoreload pair that gets closest to the
+// target frequency, and shortens the table if the sample rate would be too high.
+
+// Fewest timer ticks between two samples that DMA and DAC can keep up with.
+pub const MIN_TICKS_PER_SAMPLE: u32 = 16;
+
+const MAX_DIVIDER: u64 = 0x1_0000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct FrequencyPlan {
+    pub prescaler: u16,
+    pub autoreload: u16,
+    // Equal to the requested length unless the table had to be shortened;
+    // the caller then has to regenerate the table with this many samples.
+    pub table_len: usize,
+    pub achieved_millihertz: u64,
+    pub error_ppm: i32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PlanError {
+    ZeroFrequency,
+    EmptyTable,
+    // Even the largest dividers give a higher frequency than requested.
+    TooLow,
+    // Not even a two-sample table can be played this fast.
+    TooHigh,
+}
+
+// Frequencies are in millihertz so sub-hertz outputs can be planned, too.
+pub fn plan_output_frequency(
+    timer_clock_hz: u32,
+    target_millihertz: u64,
+    table_len: usize,
+) -> Result<FrequencyPlan, PlanError> {
+    if target_millihertz == 0 {
+        return Err(PlanError::ZeroFrequency);
+    }
+    if table_len == 0 {
+        return Err(PlanError::EmptyTable);
+    }
+
+    // Everything below is scaled by 1000 along with the target frequency.
+    let clock = timer_clock_hz as u64 * 1000;
+    let target = target_millihertz;
+
+    let mut len = table_len as u64;
+    if clock / MIN_TICKS_PER_SAMPLE as u64 / len < target {
+        len = clock / MIN_TICKS_PER_SAMPLE as u64 / target;
+        if len < 2 {
+            return Err(PlanError::TooHigh);
+        }
+    }
+
+    let sample_rate = target * len;
+    if clock / MAX_DIVIDER / MAX_DIVIDER >= sample_rate {
+        return Err(PlanError::TooLow);
+    }
+
+    // Try every prescaler that still allows a fitting autoreload and keep the
+    // closest result; the first one found wins ties, which keeps the
+    // prescaler small and the autoreload (resolution) large. A pair with the
+    // prescaler above the autoreload does no better than the swapped pair, so
+    // prescalers stop at the square root of the total divider. That bounds
+    // the search to 16384 candidates (total dividers around 2^30), each
+    // costing two u64 divisions.
+    let mut best = (0, 0, u64::MAX);
+    let mut psc_divider = clock.div_ceil(sample_rate * MAX_DIVIDER).max(1);
+    let last_psc_divider = ((clock / sample_rate).isqrt() + 1)
+        .max(psc_divider)
+        .min(MAX_DIVIDER);
+    while psc_divider <= last_psc_divider {
+        let step = sample_rate * psc_divider;
+        let car_divider = (clock + step / 2) / step;
+        if car_divider == 0 {
+            break;
+        }
+        if car_divider <= MAX_DIVIDER {
+            let period = step * car_divider;
+            let error = period.abs_diff(clock) * 1_000_000 / clock;
+            if error < best.2 {
+                best = (psc_divider, car_divider, error);
+                if error == 0 {
+                    break;
+                }
+            }
+        }
+        psc_divider += 1;
+    }
+
+    let (psc_divider, car_divider, _) = best;
+    let ticks_per_period = psc_divider * car_divider * len;
+    let achieved_millihertz = (clock + ticks_per_period / 2) / ticks_per_period;
+    let error_ppm = (achieved_millihertz as i64 - target as i64) * 1_000_000 / target as i64;
+
+    Ok(FrequencyPlan {
+        prescaler: (psc_divider - 1) as u16,
+        autoreload: (car_divider - 1) as u16,
+        table_len: len as usize,
+        achieved_millihertz,
+        error_ppm: error_ppm as i32,
+    })
+}
+
+impl FrequencyPlan {
+    pub fn apply(&self, timer_periph: u32) {
+        timer_prescaler_config(timer_periph, self.prescaler, TIMER_PSC_RELOAD_UPDATE);
+        timer_autoreload_value_config(timer_periph, self.autoreload);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exact_division() {
+        let plan = plan_output_frequency(8_000_000, 1_000_000, 32).unwrap();
+
+        assert_eq!(plan.prescaler, 0);
+        assert_eq!(plan.autoreload, 249);
+        assert_eq!(plan.table_len, 32);
+        assert_eq!(plan.achieved_millihertz, 1_000_000);
+        assert_eq!(plan.error_ppm, 0);
+    }
+
+    #[test]
+    fn needs_prescaler() {
+        // 108 MHz / (10 Hz * 100) = 108_000 ticks per sample.
+        let plan = plan_output_frequency(108_000_000, 10_000, 100).unwrap();
+
+        assert_eq!(
+            (plan.prescaler as u64 + 1) * (plan.autoreload as u64 + 1),
+            108_000
+        );
+        assert_eq!(plan.prescaler, 1);
+        assert_eq!(plan.error_ppm, 0);
+    }
+
+    #[test]
+    fn inexact_reports_error() {
+        // 8 MHz / (3 kHz * 64) = 41.67 ticks per sample, best is 42.
+        let plan = plan_output_frequency(8_000_000, 3_000_000, 64).unwrap();
+
+        assert_eq!(plan.prescaler, 0);
+        assert_eq!(plan.autoreload, 41);
+        assert_eq!(plan.achieved_millihertz, 2_976_190);
+        assert_eq!(plan.error_ppm, -7936);
+    }
+
+    #[test]
+    fn shortens_table_when_too_fast() {
+        // 8 MHz / 16 ticks / 50 kHz = 10 samples at most.
+        let plan = plan_output_frequency(8_000_000, 50_000_000, 32).unwrap();
+
+        assert_eq!(plan.table_len, 10);
+        assert_eq!(plan.prescaler, 0);
+        assert_eq!(plan.autoreload, 15);
+        assert_eq!(plan.error_ppm, 0);
+    }
+
+    #[test]
+    fn out_of_range() {
+        assert_eq!(
+            plan_output_frequency(8_000_000, 0, 32),
+            Err(PlanError::ZeroFrequency)
+        );
+        assert_eq!(
+            plan_output_frequency(8_000_000, 1_000_000, 0),
+            Err(PlanError::EmptyTable)
+        );
+        assert_eq!(
+            plan_output_frequency(8_000_000, 300_000_000, 32),
+            Err(PlanError::TooHigh)
+        );
+        assert_eq!(
+            plan_output_frequency(108_000_000, 1, 1),
+            Err(PlanError::TooLow)
+        );
+    }
+
+    #[test]
+    fn sub_hertz() {
+        // 0.5 Hz from 8 MHz with 100 samples: 160_000 ticks per sample.
+        let plan = plan_output_frequency(8_000_000, 500, 100).unwrap();
+
+        assert_eq!(
+            (plan.prescaler as u64 + 1) * (plan.autoreload as u64 + 1),
+            160_000
+        );
+        assert_eq!(plan.achieved_millihertz, 500);
+    }
+
+    // Errors are compared in whole ppm, like the planner does.
+    #[test]
+    fn bounded_search_matches_exhaustive() {
+        for (clock, target, len) in [
+            (108_000_000, 3_000_000, 64),
+            (108_000_000, 7_777, 100),
+            (8_000_000, 1_234_567, 32),
+            (54_000_000, 333, 16),
+        ] {
+            let plan = plan_output_frequency(clock, target, len).unwrap();
+            let clock = clock as u64 * 1000;
+            let sample_rate = target * len as u64;
+            let best = (1..=MAX_DIVIDER)
+                .map(|psc| {
+                    let step = sample_rate * psc;
+                    let car = ((clock + step / 2) / step).clamp(1, MAX_DIVIDER);
+                    (step * car).abs_diff(clock) * 1_000_000 / clock
+                })
+                .min()
+                .unwrap();
+            let divider = (plan.prescaler as u64 + 1) * (plan.autoreload as u64 + 1);
+            let error_ppm = (sample_rate * divider).abs_diff(clock) * 1_000_000 / clock;
+            assert_eq!(error_ppm, best);
+        }
+    }
+}
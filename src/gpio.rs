@@ -1,49 +1,107 @@
+use crate::rcu::{rcu_periph_reset_pulse, RcuPeriph};
 use crate::register_helpers::*;
+use crate::singleton::singleton;
+use core::marker::PhantomData;
 
 /* GPIO constants */
-pub(crate) const GPIO_MODE_AIN: u32 = 0x00;
-const GPIO_MODE_IPU: u32 = 0x48;
-const GPIO_MODE_IPD: u32 = 0x28;
+pub const GPIO_MODE_AIN: u32 = 0x00; // analog input
+pub const GPIO_MODE_IN_FLOATING: u32 = 0x04; // floating input
+pub const GPIO_MODE_IPD: u32 = 0x28; // input with pull-down
+pub const GPIO_MODE_IPU: u32 = 0x48; // input with pull-up
+pub const GPIO_MODE_OUT_OD: u32 = 0x14; // open-drain output
+pub const GPIO_MODE_OUT_PP: u32 = 0x10; // push-pull output
+pub const GPIO_MODE_AF_OD: u32 = 0x1C; // alternate function open-drain
+pub const GPIO_MODE_AF_PP: u32 = 0x18; // alternate function push-pull
 
-pub(crate) const GPIO_OSPEED_50MHZ: u32 = 0x03;
+// Output speeds, only used by the output and alternate function modes.
+pub const GPIO_OSPEED_10MHZ: u32 = 0x01;
+pub const GPIO_OSPEED_2MHZ: u32 = 0x02;
+pub const GPIO_OSPEED_50MHZ: u32 = 0x03;
+
+// Output speed of a typed output or alternate function pin.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum GpioSpeed {
+    Mhz2,
+    Mhz10,
+    Mhz50,
+}
+
+impl GpioSpeed {
+    const fn bits(self) -> u32 {
+        match self {
+            GpioSpeed::Mhz2 => GPIO_OSPEED_2MHZ,
+            GpioSpeed::Mhz10 => GPIO_OSPEED_10MHZ,
+            GpioSpeed::Mhz50 => GPIO_OSPEED_50MHZ,
+        }
+    }
+}
+
+pub const GPIO_PIN_0: u32 = bit(0);
+pub const GPIO_PIN_1: u32 = bit(1);
+pub const GPIO_PIN_2: u32 = bit(2);
+pub const GPIO_PIN_3: u32 = bit(3);
+pub const GPIO_PIN_4: u32 = bit(4);
+pub const GPIO_PIN_5: u32 = bit(5);
+pub const GPIO_PIN_6: u32 = bit(6);
+pub const GPIO_PIN_7: u32 = bit(7);
+pub const GPIO_PIN_8: u32 = bit(8);
+pub const GPIO_PIN_9: u32 = bit(9);
+pub const GPIO_PIN_10: u32 = bit(10);
+pub const GPIO_PIN_11: u32 = bit(11);
+pub const GPIO_PIN_12: u32 = bit(12);
+pub const GPIO_PIN_13: u32 = bit(13);
+pub const GPIO_PIN_14: u32 = bit(14);
+pub const GPIO_PIN_15: u32 = bit(15);
+pub const GPIO_PIN_ALL: u32 = bits(0, 15);
 
 const APB2_BUS_BASE: u32 = 0x4001_0000;
 const GPIO_BASE: u32 = APB2_BUS_BASE + 0x0000_0800;
-pub(crate) const GPIOA: u32 = GPIO_BASE + 0x0000_0000;
+pub const GPIOA: u32 = GPIO_BASE + 0x0000_0000;
+pub const GPIOB: u32 = GPIO_BASE + 0x0000_0400;
+pub const GPIOC: u32 = GPIO_BASE + 0x0000_0800;
+pub const GPIOD: u32 = GPIO_BASE + 0x0000_0C00;
+pub const GPIOE: u32 = GPIO_BASE + 0x0000_1000;
 
 fn gpio_mode_mask(n: u32) -> u32 {
     0xf << (4 * n)
 }
 
 fn gpio_mode_set(n: u32, mode: u32) -> u32 {
-    mode << (4 * n)
+    (mode & 0xf) << (4 * n)
 }
 
-fn gpio_bc(gpiox: u32) -> *mut u32 {
+fn gpio_bc(gpiox: u32) -> Reg32 {
     reg32(gpiox + 0x14)
 }
 
-const fn gpio_bop(gpiox: u32) -> *mut u32 {
+const fn gpio_bop(gpiox: u32) -> Reg32 {
     reg32(gpiox + 0x10)
 }
 
-const fn gpio_ctl1(gpiox: u32) -> *mut u32 {
+const fn gpio_ctl1(gpiox: u32) -> Reg32 {
     reg32(gpiox + 0x04)
 }
 
-fn gpio_bit_set(gpio_periph: u32, pin: u32) {
-    set_register(gpio_bop(gpio_periph), pin);
+const fn gpio_istat(gpiox: u32) -> Reg32 {
+    reg32(gpiox + 0x08)
+}
+
+const fn gpio_octl(gpiox: u32) -> Reg32 {
+    reg32(gpiox + 0x0C)
 }
 
-fn gpio_ctl0(gpiox: u32) -> *mut u32 {
+// BOP sets the pins in its lower half and clears the ones in its upper half.
+const GPIO_BOP_CR_OFFSET: u32 = 16;
+
+fn gpio_ctl0(gpiox: u32) -> Reg32 {
     reg32(gpiox + 0x00)
 }
 
 pub fn gpio_init(gpio_periph: u32, mode: u32, speed: u32, pin: u32) {
-    let mut temp_mode = (mode & 0x0fu32) as u32;
+    let mut temp_mode = mode & 0x0fu32;
 
     if 0x00 != (mode & 0x10) {
-        temp_mode |= speed;
+        temp_mode |= speed & 0x03;
     }
     for i in 0..8u32 {
         if (1u32 << i) & pin != 0 {
@@ -86,3 +144,476 @@ pub fn gpio_init(gpio_periph: u32, mode: u32, speed: u32, pin: u32) {
         }
     }
 }
+
+const fn gpio_rcu_periph(gpio_periph: u32) -> Option<RcuPeriph> {
+    match gpio_periph {
+        GPIOA => Some(RcuPeriph::GpioA),
+        GPIOB => Some(RcuPeriph::GpioB),
+        GPIOC => Some(RcuPeriph::GpioC),
+        GPIOD => Some(RcuPeriph::GpioD),
+        GPIOE => Some(RcuPeriph::GpioE),
+        _ => None,
+    }
+}
+
+pub fn gpio_deinit(gpio_periph: u32) -> Option<()> {
+    rcu_periph_reset_pulse(gpio_rcu_periph(gpio_periph)?)
+}
+
+/* Pin and port access */
+// `pin` is a mask of GPIO_PIN_x constants. Writes go through BOP/BC, so they
+// only touch the given pins and never race with other pins of the port.
+
+pub fn gpio_bit_set(gpio_periph: u32, pin: u32) {
+    set_register(gpio_bop(gpio_periph), pin);
+}
+
+pub fn gpio_bit_reset(gpio_periph: u32, pin: u32) {
+    set_register(gpio_bc(gpio_periph), pin);
+}
+
+pub fn gpio_bit_write(gpio_periph: u32, pin: u32, value: bool) {
+    if value {
+        gpio_bit_set(gpio_periph, pin);
+    } else {
+        gpio_bit_reset(gpio_periph, pin);
+    }
+}
+
+// Inverts the pins with a single BOP write.
+pub fn gpio_bit_toggle(gpio_periph: u32, pin: u32) {
+    let octl = read_register(gpio_octl(gpio_periph));
+    let set = !octl & pin;
+    let clear = octl & pin;
+    set_register(gpio_bop(gpio_periph), set | (clear << GPIO_BOP_CR_OFFSET));
+}
+
+pub fn gpio_port_write(gpio_periph: u32, data: u16) {
+    set_register(gpio_octl(gpio_periph), data as u32);
+}
+
+// Level on the pin, whatever its mode.
+pub fn gpio_input_bit_get(gpio_periph: u32, pin: u32) -> bool {
+    read_register(gpio_istat(gpio_periph)) & pin != 0
+}
+
+pub fn gpio_input_port_get(gpio_periph: u32) -> u16 {
+    read_register(gpio_istat(gpio_periph)) as u16
+}
+
+// Level the output register drives the pin to.
+pub fn gpio_output_bit_get(gpio_periph: u32, pin: u32) -> bool {
+    read_register(gpio_octl(gpio_periph)) & pin != 0
+}
+
+pub fn gpio_output_port_get(gpio_periph: u32) -> u16 {
+    read_register(gpio_octl(gpio_periph)) as u16
+}
+
+/* AFIO */
+// The alternate-function I/O block also selects which port drives each EXTI
+// line: line n can be fed by pin n of any port.
+const AFIO: u32 = APB2_BUS_BASE + 0x0000_0000;
+
+const fn afio_extiss(line: u8) -> Reg32 {
+    reg32(AFIO + 0x08 + 4 * (line as u32 / 4))
+}
+
+const AFIO_EXTI_SOURCE_FIELDS: u32 = 0xf;
+
+const fn gpio_port_source(gpio_periph: u32) -> Option<u32> {
+    match gpio_periph {
+        GPIOA => Some(0),
+        GPIOB => Some(1),
+        GPIOC => Some(2),
+        GPIOD => Some(3),
+        GPIOE => Some(4),
+        _ => None,
+    }
+}
+
+// Routes pin `pin_number` (0..=15) of `gpio_periph` to EXTI line
+// `pin_number`. Needs the AFIO clock (`RcuPeriph::Af`).
+pub fn gpio_exti_source_select(gpio_periph: u32, pin_number: u8) -> Option<()> {
+    let port = gpio_port_source(gpio_periph)?;
+    if pin_number > 15 {
+        return None;
+    }
+    let shift = 4 * (pin_number as u32 % 4);
+    let mut extiss = read_register(afio_extiss(pin_number));
+    extiss &= !(AFIO_EXTI_SOURCE_FIELDS << shift);
+    extiss |= port << shift;
+    set_register(afio_extiss(pin_number), extiss);
+    Some(())
+}
+
+/* Type-state pins */
+// Each port can be taken once and split into its pins. A pin's type records
+// its mode, e.g. `PA4<Analog>` or `PC13<Output<PushPull>>`, and changes with
+// every `into_*` conversion, so a driver can require a pin in the right mode.
+// Pins start out as floating inputs, the reset state.
+
+pub struct Analog;
+
+pub struct Input<MODE> {
+    _mode: PhantomData<MODE>,
+}
+
+pub struct Floating;
+pub struct PullUp;
+pub struct PullDown;
+
+pub struct Output<MODE> {
+    _mode: PhantomData<MODE>,
+}
+
+pub struct PushPull;
+pub struct OpenDrain;
+
+pub struct Alternate<MODE> {
+    _mode: PhantomData<MODE>,
+}
+
+macro_rules! gpio_port {
+    ($Gpiox:ident, $taken:ident, $periph:expr, $Parts:ident {
+        $($pxi:ident: $PXi:ident = $i:expr),*
+    }) => {
+        singleton!($Gpiox, $taken, "this port or its pins");
+
+        impl $Gpiox {
+            pub fn split(self) -> $Parts {
+                $Parts {
+                    $($pxi: $PXi { _mode: PhantomData }),*
+                }
+            }
+        }
+
+        pub struct $Parts {
+            $(pub $pxi: $PXi<Input<Floating>>),*
+        }
+
+        $(
+            pub struct $PXi<MODE> {
+                _mode: PhantomData<MODE>,
+            }
+
+            impl<MODE> $PXi<MODE> {
+                const PIN: u32 = bit($i);
+
+                fn into_mode<NEW>(self, mode: u32, speed: u32) -> $PXi<NEW> {
+                    gpio_init($periph, mode, speed, Self::PIN);
+                    $PXi { _mode: PhantomData }
+                }
+
+                pub fn into_analog(self) -> $PXi<Analog> {
+                    self.into_mode(GPIO_MODE_AIN, GPIO_OSPEED_50MHZ)
+                }
+
+                pub fn into_floating_input(self) -> $PXi<Input<Floating>> {
+                    self.into_mode(GPIO_MODE_IN_FLOATING, GPIO_OSPEED_50MHZ)
+                }
+
+                pub fn into_pull_up_input(self) -> $PXi<Input<PullUp>> {
+                    self.into_mode(GPIO_MODE_IPU, GPIO_OSPEED_50MHZ)
+                }
+
+                pub fn into_pull_down_input(self) -> $PXi<Input<PullDown>> {
+                    self.into_mode(GPIO_MODE_IPD, GPIO_OSPEED_50MHZ)
+                }
+
+                pub fn into_push_pull_output(self, speed: GpioSpeed) -> $PXi<Output<PushPull>> {
+                    self.into_mode(GPIO_MODE_OUT_PP, speed.bits())
+                }
+
+                pub fn into_open_drain_output(self, speed: GpioSpeed) -> $PXi<Output<OpenDrain>> {
+                    self.into_mode(GPIO_MODE_OUT_OD, speed.bits())
+                }
+
+                pub fn into_alternate_push_pull(
+                    self,
+                    speed: GpioSpeed,
+                ) -> $PXi<Alternate<PushPull>> {
+                    self.into_mode(GPIO_MODE_AF_PP, speed.bits())
+                }
+
+                pub fn into_alternate_open_drain(
+                    self,
+                    speed: GpioSpeed,
+                ) -> $PXi<Alternate<OpenDrain>> {
+                    self.into_mode(GPIO_MODE_AF_OD, speed.bits())
+                }
+            }
+
+            impl<MODE> $PXi<Output<MODE>> {
+                pub fn set_high(&mut self) {
+                    gpio_bit_set($periph, Self::PIN);
+                }
+
+                pub fn set_low(&mut self) {
+                    gpio_bit_reset($periph, Self::PIN);
+                }
+
+                pub fn toggle(&mut self) {
+                    gpio_bit_toggle($periph, Self::PIN);
+                }
+
+                pub fn is_set_high(&self) -> bool {
+                    gpio_output_bit_get($periph, Self::PIN)
+                }
+            }
+
+            impl<MODE> $PXi<Input<MODE>> {
+                pub fn is_high(&self) -> bool {
+                    gpio_input_bit_get($periph, Self::PIN)
+                }
+
+                pub fn is_low(&self) -> bool {
+                    !self.is_high()
+                }
+            }
+        )*
+    };
+}
+
+gpio_port!(
+    Gpioa,
+    GPIOA_TAKEN,
+    GPIOA,
+    GpioaParts {
+        pa0: PA0 = 0,
+        pa1: PA1 = 1,
+        pa2: PA2 = 2,
+        pa3: PA3 = 3,
+        pa4: PA4 = 4,
+        pa5: PA5 = 5,
+        pa6: PA6 = 6,
+        pa7: PA7 = 7,
+        pa8: PA8 = 8,
+        pa9: PA9 = 9,
+        pa10: PA10 = 10,
+        pa11: PA11 = 11,
+        pa12: PA12 = 12,
+        pa13: PA13 = 13,
+        pa14: PA14 = 14,
+        pa15: PA15 = 15
+    }
+);
+
+gpio_port!(
+    Gpiob,
+    GPIOB_TAKEN,
+    GPIOB,
+    GpiobParts {
+        pb0: PB0 = 0,
+        pb1: PB1 = 1,
+        pb2: PB2 = 2,
+        pb3: PB3 = 3,
+        pb4: PB4 = 4,
+        pb5: PB5 = 5,
+        pb6: PB6 = 6,
+        pb7: PB7 = 7,
+        pb8: PB8 = 8,
+        pb9: PB9 = 9,
+        pb10: PB10 = 10,
+        pb11: PB11 = 11,
+        pb12: PB12 = 12,
+        pb13: PB13 = 13,
+        pb14: PB14 = 14,
+        pb15: PB15 = 15
+    }
+);
+
+gpio_port!(
+    Gpioc,
+    GPIOC_TAKEN,
+    GPIOC,
+    GpiocParts {
+        pc0: PC0 = 0,
+        pc1: PC1 = 1,
+        pc2: PC2 = 2,
+        pc3: PC3 = 3,
+        pc4: PC4 = 4,
+        pc5: PC5 = 5,
+        pc6: PC6 = 6,
+        pc7: PC7 = 7,
+        pc8: PC8 = 8,
+        pc9: PC9 = 9,
+        pc10: PC10 = 10,
+        pc11: PC11 = 11,
+        pc12: PC12 = 12,
+        pc13: PC13 = 13,
+        pc14: PC14 = 14,
+        pc15: PC15 = 15
+    }
+);
+
+gpio_port!(
+    Gpiod,
+    GPIOD_TAKEN,
+    GPIOD,
+    GpiodParts {
+        pd0: PD0 = 0,
+        pd1: PD1 = 1,
+        pd2: PD2 = 2,
+        pd3: PD3 = 3,
+        pd4: PD4 = 4,
+        pd5: PD5 = 5,
+        pd6: PD6 = 6,
+        pd7: PD7 = 7,
+        pd8: PD8 = 8,
+        pd9: PD9 = 9,
+        pd10: PD10 = 10,
+        pd11: PD11 = 11,
+        pd12: PD12 = 12,
+        pd13: PD13 = 13,
+        pd14: PD14 = 14,
+        pd15: PD15 = 15
+    }
+);
+
+gpio_port!(
+    Gpioe,
+    GPIOE_TAKEN,
+    GPIOE,
+    GpioeParts {
+        pe0: PE0 = 0,
+        pe1: PE1 = 1,
+        pe2: PE2 = 2,
+        pe3: PE3 = 3,
+        pe4: PE4 = 4,
+        pe5: PE5 = 5,
+        pe6: PE6 = 6,
+        pe7: PE7 = 7,
+        pe8: PE8 = 8,
+        pe9: PE9 = 9,
+        pe10: PE10 = 10,
+        pe11: PE11 = 11,
+        pe12: PE12 = 12,
+        pe13: PE13 = 13,
+        pe14: PE14 = 14,
+        pe15: PE15 = 15
+    }
+);
+
+// Pin 9 of any port as an input, floating or pulled, which can drive EXTI
+// line 9.
+pub trait Exti9Pin {
+    const PERIPH: u32;
+}
+
+impl<MODE> Exti9Pin for PA9<Input<MODE>> {
+    const PERIPH: u32 = GPIOA;
+}
+
+impl<MODE> Exti9Pin for PB9<Input<MODE>> {
+    const PERIPH: u32 = GPIOB;
+}
+
+impl<MODE> Exti9Pin for PC9<Input<MODE>> {
+    const PERIPH: u32 = GPIOC;
+}
+
+impl<MODE> Exti9Pin for PD9<Input<MODE>> {
+    const PERIPH: u32 = GPIOD;
+}
+
+impl<MODE> Exti9Pin for PE9<Input<MODE>> {
+    const PERIPH: u32 = GPIOE;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::sim;
+
+    const GPIOC_CTL1: u32 = 0x4001_1004;
+    const GPIOC_ISTAT: u32 = 0x4001_1008;
+    const GPIOC_OCTL: u32 = 0x4001_100c;
+    const GPIOC_BOP: u32 = 0x4001_1010;
+    const GPIOC_BC: u32 = 0x4001_1014;
+
+    #[test]
+    fn init_output_and_pull_up_modes() {
+        sim::reset();
+        sim::preset(GPIOC_CTL1, 0x4444_4444);
+
+        gpio_init(GPIOC, GPIO_MODE_OUT_PP, GPIO_OSPEED_2MHZ, GPIO_PIN_13);
+        assert_eq!(sim::peek(GPIOC_CTL1), 0x4424_4444);
+
+        gpio_init(GPIOC, GPIO_MODE_AF_OD, GPIO_OSPEED_50MHZ, GPIO_PIN_8);
+        assert_eq!(sim::peek(GPIOC_CTL1), 0x4424_444f);
+
+        gpio_init(GPIOC, GPIO_MODE_IPU, GPIO_OSPEED_50MHZ, GPIO_PIN_15);
+        assert_eq!(sim::peek(GPIOC_CTL1), 0x8424_444f);
+        assert_eq!(sim::peek(GPIOC_BOP), GPIO_PIN_15);
+
+        // Out of range values stay within the pin's own field.
+        gpio_init(GPIOC, 0xff, 0xff, GPIO_PIN_12);
+        assert_eq!(sim::peek(GPIOC_CTL1), 0x842f_444f);
+    }
+
+    #[test]
+    fn set_reset_and_toggle() {
+        sim::reset();
+
+        gpio_bit_write(GPIOC, GPIO_PIN_13, true);
+        assert_eq!(sim::take_writes(), [(GPIOC_BOP, GPIO_PIN_13)]);
+        gpio_bit_write(GPIOC, GPIO_PIN_13, false);
+        assert_eq!(sim::take_writes(), [(GPIOC_BC, GPIO_PIN_13)]);
+
+        sim::preset(GPIOC_OCTL, GPIO_PIN_0);
+        gpio_bit_toggle(GPIOC, GPIO_PIN_0 | GPIO_PIN_1);
+        assert_eq!(
+            sim::take_writes(),
+            [(GPIOC_BOP, GPIO_PIN_1 | (GPIO_PIN_0 << 16))]
+        );
+    }
+
+    #[test]
+    fn pin_conversions_program_their_mode() {
+        sim::reset();
+        sim::preset(GPIOC_CTL1, 0x4444_4444);
+        let gpioc = unsafe { Gpioc::steal() }.split();
+
+        let mut led = gpioc.pc13.into_push_pull_output(GpioSpeed::Mhz2);
+        let button = gpioc.pc14.into_pull_down_input();
+        assert_eq!(sim::peek(GPIOC_CTL1), 0x4824_4444);
+        assert_eq!(sim::peek(GPIOC_BC), GPIO_PIN_14);
+
+        led.set_low();
+        assert_eq!(sim::peek(GPIOC_BC), GPIO_PIN_13);
+        sim::preset(GPIOC_ISTAT, GPIO_PIN_14);
+        assert!(button.is_high());
+
+        let _ = led.into_analog();
+        assert_eq!(sim::peek(GPIOC_CTL1), 0x4804_4444);
+    }
+
+    #[test]
+    fn exti_source_select() {
+        const AFIO_EXTISS2: u32 = 0x4001_0010;
+        sim::reset();
+        sim::preset(AFIO_EXTISS2, 0x1111);
+
+        assert_eq!(gpio_exti_source_select(GPIOC, 9), Some(()));
+        assert_eq!(sim::peek(AFIO_EXTISS2), 0x1121);
+        assert_eq!(gpio_exti_source_select(GPIOC, 16), None);
+        assert_eq!(gpio_exti_source_select(0x4001_2000, 9), None);
+    }
+
+    #[test]
+    fn read_input_and_output() {
+        sim::reset();
+        sim::preset(GPIOC_ISTAT, 0xdead_2001);
+        sim::preset(GPIOC_OCTL, 0x0000_8000);
+
+        assert!(gpio_input_bit_get(GPIOC, GPIO_PIN_13));
+        assert!(!gpio_input_bit_get(GPIOC, GPIO_PIN_1));
+        assert_eq!(gpio_input_port_get(GPIOC), 0x2001);
+        assert!(gpio_output_bit_get(GPIOC, GPIO_PIN_15));
+        assert_eq!(gpio_output_port_get(GPIOC), 0x8000);
+
+        gpio_port_write(GPIOC, 0x00ff);
+        assert_eq!(sim::peek(GPIOC_OCTL), 0x00ff);
+    }
+}
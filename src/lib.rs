@@ -0,0 +1,21 @@
+#![cfg_attr(target_arch = "riscv32", no_std)]
+// Register maps spell out zero offsets, like the reference manual does.
+#![allow(clippy::identity_op)]
+
+pub mod bus;
+pub mod chirp;
+pub mod config;
+pub mod csr;
+pub mod dac;
+pub mod delay;
+pub mod dma;
+pub mod eclic;
+pub mod exti;
+pub mod frequency;
+pub mod gpio;
+pub mod rcu;
+pub mod register_helpers;
+mod singleton;
+pub mod timer;
+pub mod trap;
+pub mod waveform;
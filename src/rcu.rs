@@ -1,43 +1,667 @@
 use crate::register_helpers::*;
 
 /* RCU constants */
-const RCU_AHB1_BUS_BASE: u32 = (0x4001_8000);
+const RCU_AHB1_BUS_BASE: u32 = 0x4001_8000;
 const RCU_BASE: u32 = RCU_AHB1_BUS_BASE + 0x0000_9000;
 const RCU: u32 = RCU_BASE;
 
-const APB1EN_REG_OFFSET: u32 = 0x1c;
-const APB2EN_REG_OFFSET: u32 = 0x18;
 const AHBEN_REG_OFFSET: u32 = 0x14;
+const APB2EN_REG_OFFSET: u32 = 0x18;
+const APB1EN_REG_OFFSET: u32 = 0x1c;
 
-pub(crate) const RCU_GPIOA: u32 = rcu_regidx_bit(APB2EN_REG_OFFSET, 2);
-pub(crate) const RCU_GPIOC: u32 = rcu_regidx_bit(APB2EN_REG_OFFSET, 4);
+const APB2RST_REG_OFFSET: u32 = 0x0c;
+const APB1RST_REG_OFFSET: u32 = 0x10;
+const AHBRST_REG_OFFSET: u32 = 0x28;
 
-pub(crate) const RCU_DAC: u32 = rcu_regidx_bit(APB1EN_REG_OFFSET, 29);
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RcuPeriph {
+    // AHB
+    Dma0,
+    Dma1,
+    Crc,
+    Exmc,
+    Usbfs,
+    // APB2
+    Af,
+    GpioA,
+    GpioB,
+    GpioC,
+    GpioD,
+    GpioE,
+    Adc0,
+    Adc1,
+    Timer0,
+    Spi0,
+    Usart0,
+    // APB1
+    Timer1,
+    Timer2,
+    Timer3,
+    Timer4,
+    Timer5,
+    Timer6,
+    Wwdgt,
+    Spi1,
+    Spi2,
+    Usart1,
+    Usart2,
+    Uart3,
+    Uart4,
+    I2c0,
+    I2c1,
+    Can0,
+    Can1,
+    Bkpi,
+    Pmu,
+    Dac,
+}
+
+impl RcuPeriph {
+    // Bus and bit position; the enable and reset registers of a bus share them.
+    const fn bus_bit(self) -> (RcuBus, u32) {
+        use RcuPeriph::*;
+        match self {
+            Dma0 => (RcuBus::Ahb, 0),
+            Dma1 => (RcuBus::Ahb, 1),
+            Crc => (RcuBus::Ahb, 6),
+            Exmc => (RcuBus::Ahb, 8),
+            Usbfs => (RcuBus::Ahb, 12),
+            Af => (RcuBus::Apb2, 0),
+            GpioA => (RcuBus::Apb2, 2),
+            GpioB => (RcuBus::Apb2, 3),
+            GpioC => (RcuBus::Apb2, 4),
+            GpioD => (RcuBus::Apb2, 5),
+            GpioE => (RcuBus::Apb2, 6),
+            Adc0 => (RcuBus::Apb2, 9),
+            Adc1 => (RcuBus::Apb2, 10),
+            Timer0 => (RcuBus::Apb2, 11),
+            Spi0 => (RcuBus::Apb2, 12),
+            Usart0 => (RcuBus::Apb2, 14),
+            Timer1 => (RcuBus::Apb1, 0),
+            Timer2 => (RcuBus::Apb1, 1),
+            Timer3 => (RcuBus::Apb1, 2),
+            Timer4 => (RcuBus::Apb1, 3),
+            Timer5 => (RcuBus::Apb1, 4),
+            Timer6 => (RcuBus::Apb1, 5),
+            Wwdgt => (RcuBus::Apb1, 11),
+            Spi1 => (RcuBus::Apb1, 14),
+            Spi2 => (RcuBus::Apb1, 15),
+            Usart1 => (RcuBus::Apb1, 17),
+            Usart2 => (RcuBus::Apb1, 18),
+            Uart3 => (RcuBus::Apb1, 19),
+            Uart4 => (RcuBus::Apb1, 20),
+            I2c0 => (RcuBus::Apb1, 21),
+            I2c1 => (RcuBus::Apb1, 22),
+            Can0 => (RcuBus::Apb1, 25),
+            Can1 => (RcuBus::Apb1, 26),
+            Bkpi => (RcuBus::Apb1, 27),
+            Pmu => (RcuBus::Apb1, 28),
+            Dac => (RcuBus::Apb1, 29),
+        }
+    }
 
-pub(crate) const RCU_DMA1: u32 = rcu_regidx_bit(AHBEN_REG_OFFSET, 1);
+    const fn enable_bit(self) -> u32 {
+        let (bus, bitpos) = self.bus_bit();
+        match bus {
+            RcuBus::Ahb => rcu_regidx_bit(AHBEN_REG_OFFSET, bitpos),
+            RcuBus::Apb2 => rcu_regidx_bit(APB2EN_REG_OFFSET, bitpos),
+            RcuBus::Apb1 => rcu_regidx_bit(APB1EN_REG_OFFSET, bitpos),
+        }
+    }
 
-pub(crate) const RCU_TIMER5: u32 = rcu_regidx_bit(APB1EN_REG_OFFSET, 4);
+    // Of the AHB peripherals, only USBFS can be reset.
+    const fn reset_bit(self) -> Option<u32> {
+        let (bus, bitpos) = self.bus_bit();
+        match (bus, self) {
+            (RcuBus::Ahb, RcuPeriph::Usbfs) => Some(rcu_regidx_bit(AHBRST_REG_OFFSET, bitpos)),
+            (RcuBus::Ahb, _) => None,
+            (RcuBus::Apb2, _) => Some(rcu_regidx_bit(APB2RST_REG_OFFSET, bitpos)),
+            (RcuBus::Apb1, _) => Some(rcu_regidx_bit(APB1RST_REG_OFFSET, bitpos)),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+enum RcuBus {
+    Ahb,
+    Apb2,
+    Apb1,
+}
+
+// Memories whose clock can be kept running in sleep mode.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum RcuSleepPeriph {
+    Sram,
+    Fmc,
+}
+
+impl RcuSleepPeriph {
+    const fn enable_bit(self) -> u32 {
+        match self {
+            RcuSleepPeriph::Sram => rcu_regidx_bit(AHBEN_REG_OFFSET, 2),
+            RcuSleepPeriph::Fmc => rcu_regidx_bit(AHBEN_REG_OFFSET, 4),
+        }
+    }
+}
 
-pub const fn rcu_regidx_bit(regidx: u32, bitpos: u32) -> u32 {
-    ((regidx << 6) as u32) | bitpos
+const fn rcu_regidx_bit(regidx: u32, bitpos: u32) -> u32 {
+    (regidx << 6) | bitpos
 }
 
-pub fn rcu_periph_clock_enable(periph: u32) {
-    set_bits(rcu_reg_val(periph), bit(rcu_bit_pos(periph)));
+pub fn rcu_periph_clock_enable(periph: RcuPeriph) {
+    let bit_idx = periph.enable_bit();
+    set_bits(rcu_reg_val(bit_idx), bit(rcu_bit_pos(bit_idx)));
 }
 
-pub fn rcu_periph_reset_enable(periph_reset: u32) {
-    set_bits(rcu_reg_val(periph_reset), bit(rcu_bit_pos(periph_reset)));
+pub fn rcu_periph_clock_disable(periph: RcuPeriph) {
+    let bit_idx = periph.enable_bit();
+    reset_bits(rcu_reg_val(bit_idx), bit(rcu_bit_pos(bit_idx)));
 }
 
-pub fn rcu_periph_reset_disable(periph_reset: u32) {
-    reset_bits(rcu_reg_val(periph_reset), bit(rcu_bit_pos(periph_reset)));
+pub fn rcu_periph_clock_sleep_enable(periph: RcuSleepPeriph) {
+    let bit_idx = periph.enable_bit();
+    set_bits(rcu_reg_val(bit_idx), bit(rcu_bit_pos(bit_idx)));
+}
+
+pub fn rcu_periph_clock_sleep_disable(periph: RcuSleepPeriph) {
+    let bit_idx = periph.enable_bit();
+    reset_bits(rcu_reg_val(bit_idx), bit(rcu_bit_pos(bit_idx)));
+}
+
+pub fn rcu_periph_reset_enable(periph: RcuPeriph) -> Option<()> {
+    let bit_idx = periph.reset_bit()?;
+    set_bits(rcu_reg_val(bit_idx), bit(rcu_bit_pos(bit_idx)));
+    Some(())
+}
+
+pub fn rcu_periph_reset_disable(periph: RcuPeriph) -> Option<()> {
+    let bit_idx = periph.reset_bit()?;
+    reset_bits(rcu_reg_val(bit_idx), bit(rcu_bit_pos(bit_idx)));
+    Some(())
+}
+
+// Puts the peripheral's registers back to their reset values. Returns `None` for
+// peripherals without a reset bit (DMA0, DMA1, CRC, EXMC).
+pub fn rcu_periph_reset_pulse(periph: RcuPeriph) -> Option<()> {
+    rcu_periph_reset_enable(periph)?;
+    rcu_periph_reset_disable(periph)
 }
 
 fn rcu_bit_pos(val: u32) -> u32 {
-    (val & 0x1f) as u32
+    val & 0x1f
+}
+
+fn rcu_reg_val(periph: u32) -> Reg32 {
+    reg32(RCU + (periph >> 6))
+}
+
+/* Clock tree */
+const RCU_CTL: Reg32 = reg32(RCU + 0x00);
+const RCU_CFG0: Reg32 = reg32(RCU + 0x04);
+const RCU_CFG1: Reg32 = reg32(RCU + 0x2c);
+
+const RCU_CTL_HXTALEN: u32 = bit(16);
+const RCU_CTL_HXTALSTB: u32 = bit(17);
+const RCU_CTL_PLLEN: u32 = bit(24);
+const RCU_CTL_PLLSTB: u32 = bit(25);
+
+const RCU_CFG0_SCS: u32 = bits(0, 1);
+const RCU_CFG0_SCSS: u32 = bits(2, 3);
+const RCU_CFG0_AHBPSC: u32 = bits(4, 7);
+const RCU_CFG0_APB1PSC: u32 = bits(8, 10);
+const RCU_CFG0_APB2PSC: u32 = bits(11, 13);
+const RCU_CFG0_PLLSEL: u32 = bit(16);
+const RCU_CFG0_PLLMF: u32 = bits(18, 21);
+const RCU_CFG0_PLLMF_4: u32 = bit(29);
+
+const RCU_CFG1_PREDV0: u32 = bits(0, 3);
+const RCU_CFG1_PREDV0SEL: u32 = bit(16);
+
+const fn cfg0_scs(regval: u32) -> u32 {
+    bits(0, 1) & regval
+}
+
+const fn cfg0_ahbpsc(regval: u32) -> u32 {
+    bits(4, 7) & (regval << 4)
+}
+
+const fn cfg0_apb1psc(regval: u32) -> u32 {
+    bits(8, 10) & (regval << 8)
+}
+
+const fn cfg0_apb2psc(regval: u32) -> u32 {
+    bits(11, 13) & (regval << 11)
+}
+
+const fn cfg0_pllmf(regval: u32) -> u32 {
+    bits(18, 21) & (regval << 18)
+}
+
+const RCU_CKSYSSRC_IRC8M: u32 = 0;
+const RCU_CKSYSSRC_HXTAL: u32 = 1;
+const RCU_CKSYSSRC_PLL: u32 = 2;
+
+const IRC8M_VALUE: u32 = 8_000_000;
+const SYSCLK_MAX: u32 = 108_000_000;
+const PCLK1_MAX: u32 = 54_000_000;
+
+// Number of status polls before giving up on an oscillator, PLL or switch.
+const STARTUP_TIMEOUT: u32 = 0xffff;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ClockError {
+    HxtalTimeout,
+    PllTimeout,
+    SwitchTimeout,
+    SysclkTooHigh,
+    // The requested system clock can't be derived from the selected source.
+    NoPllSetting,
+}
+
+// Bus frequencies in Hz, as configured by `ClockConfig::freeze`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Clocks {
+    sysclk: u32,
+    hclk: u32,
+    pclk1: u32,
+    pclk2: u32,
+    apb1_psc: u32,
+    apb2_psc: u32,
+}
+
+impl Clocks {
+    // The clocks right after reset: IRC8M, all prescalers 1.
+    pub const fn from_reset() -> Clocks {
+        Clocks {
+            sysclk: IRC8M_VALUE,
+            hclk: IRC8M_VALUE,
+            pclk1: IRC8M_VALUE,
+            pclk2: IRC8M_VALUE,
+            apb1_psc: 1,
+            apb2_psc: 1,
+        }
+    }
+
+    pub const fn sysclk(&self) -> u32 {
+        self.sysclk
+    }
+
+    pub const fn hclk(&self) -> u32 {
+        self.hclk
+    }
+
+    pub const fn pclk1(&self) -> u32 {
+        self.pclk1
+    }
+
+    pub const fn pclk2(&self) -> u32 {
+        self.pclk2
+    }
+
+    // Clock of TIMER1..TIMER6, doubled if APB1 is divided.
+    pub const fn timer_apb1(&self) -> u32 {
+        if self.apb1_psc == 1 {
+            self.pclk1
+        } else {
+            self.pclk1 * 2
+        }
+    }
+
+    // Clock of TIMER0, doubled if APB2 is divided.
+    pub const fn timer_apb2(&self) -> u32 {
+        if self.apb2_psc == 1 {
+            self.pclk2
+        } else {
+            self.pclk2 * 2
+        }
+    }
+}
+
+// Builder for the clock tree. Without a PLL the system clock is the source
+// clock; unset bus frequencies default to the fastest allowed.
+//
+//     let clocks = ClockConfig::new()
+//         .hxtal(8_000_000)
+//         .sysclk(108_000_000)
+//         .freeze()?;
+#[derive(Copy, Clone, Debug, Default)]
+pub struct ClockConfig {
+    hxtal: Option<u32>,
+    sysclk: Option<u32>,
+    hclk: Option<u32>,
+    pclk1: Option<u32>,
+    pclk2: Option<u32>,
+}
+
+struct PllSetting {
+    predv0: u32,
+    multiplier: u32,
+}
+
+impl ClockConfig {
+    pub fn new() -> ClockConfig {
+        ClockConfig::default()
+    }
+
+    // Use an external crystal of `hz` instead of IRC8M.
+    pub fn hxtal(mut self, hz: u32) -> ClockConfig {
+        self.hxtal = Some(hz);
+        self
+    }
+
+    pub fn sysclk(mut self, hz: u32) -> ClockConfig {
+        self.sysclk = Some(hz);
+        self
+    }
+
+    pub fn hclk(mut self, hz: u32) -> ClockConfig {
+        self.hclk = Some(hz);
+        self
+    }
+
+    pub fn pclk1(mut self, hz: u32) -> ClockConfig {
+        self.pclk1 = Some(hz);
+        self
+    }
+
+    pub fn pclk2(mut self, hz: u32) -> ClockConfig {
+        self.pclk2 = Some(hz);
+        self
+    }
+
+    pub fn freeze(self) -> Result<Clocks, ClockError> {
+        let source = self.hxtal.unwrap_or(IRC8M_VALUE);
+        let sysclk = self.sysclk.unwrap_or(source);
+        if sysclk > SYSCLK_MAX {
+            return Err(ClockError::SysclkTooHigh);
+        }
+
+        let pll = if sysclk == source {
+            None
+        } else {
+            Some(self.pll_setting(sysclk).ok_or(ClockError::NoPllSetting)?)
+        };
+
+        let (ahb_psc, ahb_regval) = ahb_prescaler(sysclk, self.hclk.unwrap_or(sysclk));
+        let hclk = sysclk / ahb_psc;
+        let pclk1 = self.pclk1.unwrap_or(PCLK1_MAX).min(PCLK1_MAX);
+        let (apb1_psc, apb1_regval) = apb_prescaler(hclk, pclk1);
+        let (apb2_psc, apb2_regval) = apb_prescaler(hclk, self.pclk2.unwrap_or(hclk));
+
+        if self.hxtal.is_some() {
+            set_bits(RCU_CTL, RCU_CTL_HXTALEN);
+            wait_for(RCU_CTL, RCU_CTL_HXTALSTB, RCU_CTL_HXTALSTB)
+                .ok_or(ClockError::HxtalTimeout)?;
+        }
+
+        let mut cfg0 = read_register(RCU_CFG0);
+        cfg0 &= !(RCU_CFG0_AHBPSC | RCU_CFG0_APB1PSC | RCU_CFG0_APB2PSC);
+        cfg0 |= cfg0_ahbpsc(ahb_regval) | cfg0_apb1psc(apb1_regval) | cfg0_apb2psc(apb2_regval);
+        set_register(RCU_CFG0, cfg0);
+
+        let scs = match pll {
+            Some(pll) => {
+                // The PLL setup may only change while the PLL is off, and the
+                // PLL can't be turned off while it clocks the system.
+                if read_register(RCU_CTL) & RCU_CTL_PLLEN != 0 {
+                    if read_register(RCU_CFG0) & RCU_CFG0_SCSS == RCU_CKSYSSRC_PLL << 2 {
+                        system_clock_switch(RCU_CKSYSSRC_IRC8M)?;
+                    }
+                    reset_bits(RCU_CTL, RCU_CTL_PLLEN);
+                    wait_for(RCU_CTL, RCU_CTL_PLLSTB, 0).ok_or(ClockError::PllTimeout)?;
+                }
+
+                let mut cfg0 = read_register(RCU_CFG0);
+                cfg0 &= !(RCU_CFG0_PLLSEL | RCU_CFG0_PLLMF | RCU_CFG0_PLLMF_4);
+                cfg0 |= pllmf_bits(pll.multiplier);
+                if self.hxtal.is_some() {
+                    let mut cfg1 = read_register(RCU_CFG1);
+                    cfg1 &= !(RCU_CFG1_PREDV0 | RCU_CFG1_PREDV0SEL);
+                    cfg1 |= pll.predv0 - 1;
+                    set_register(RCU_CFG1, cfg1);
+                    cfg0 |= RCU_CFG0_PLLSEL;
+                }
+                set_register(RCU_CFG0, cfg0);
+
+                set_bits(RCU_CTL, RCU_CTL_PLLEN);
+                wait_for(RCU_CTL, RCU_CTL_PLLSTB, RCU_CTL_PLLSTB).ok_or(ClockError::PllTimeout)?;
+                RCU_CKSYSSRC_PLL
+            }
+            None if self.hxtal.is_some() => RCU_CKSYSSRC_HXTAL,
+            None => RCU_CKSYSSRC_IRC8M,
+        };
+
+        system_clock_switch(scs)?;
+
+        Ok(Clocks {
+            sysclk,
+            hclk,
+            pclk1: hclk / apb1_psc,
+            pclk2: hclk / apb2_psc,
+            apb1_psc,
+            apb2_psc,
+        })
+    }
+
+    // PLL input is HXTAL / PREDV0 (1..=16) or IRC8M / 2, times 2..=14 or 16..=32.
+    fn pll_setting(&self, sysclk: u32) -> Option<PllSetting> {
+        let predv0_range = match self.hxtal {
+            Some(_) => 1..=16,
+            None => 2..=2,
+        };
+        let source = self.hxtal.unwrap_or(IRC8M_VALUE);
+        for predv0 in predv0_range {
+            let input = source / predv0;
+            if input * predv0 != source || !sysclk.is_multiple_of(input) {
+                continue;
+            }
+            let multiplier = sysclk / input;
+            if (2..=14).contains(&multiplier) || (16..=32).contains(&multiplier) {
+                return Some(PllSetting { predv0, multiplier });
+            }
+        }
+        None
+    }
+}
+
+fn pllmf_bits(multiplier: u32) -> u32 {
+    if multiplier <= 14 {
+        cfg0_pllmf(multiplier - 2)
+    } else if multiplier == 16 {
+        cfg0_pllmf(14)
+    } else {
+        RCU_CFG0_PLLMF_4 | cfg0_pllmf(multiplier - 17)
+    }
+}
+
+// Smallest AHB prescaler that keeps the bus at or below `hclk`, as
+// (divider, AHBPSC field value).
+fn ahb_prescaler(sysclk: u32, hclk: u32) -> (u32, u32) {
+    const PRESCALERS: [u32; 9] = [1, 2, 4, 8, 16, 64, 128, 256, 512];
+    for (i, &psc) in PRESCALERS.iter().enumerate() {
+        if sysclk / psc <= hclk {
+            let regval = if i == 0 { 0 } else { 0b0111 + i as u32 };
+            return (psc, regval);
+        }
+    }
+    (512, 0b1111)
+}
+
+// Smallest APB prescaler that keeps the bus at or below `pclk`, as
+// (divider, APB1PSC/APB2PSC field value).
+fn apb_prescaler(hclk: u32, pclk: u32) -> (u32, u32) {
+    const PRESCALERS: [u32; 5] = [1, 2, 4, 8, 16];
+    for (i, &psc) in PRESCALERS.iter().enumerate() {
+        if hclk / psc <= pclk {
+            let regval = if i == 0 { 0 } else { 0b011 + i as u32 };
+            return (psc, regval);
+        }
+    }
+    (16, 0b111)
+}
+
+// Selects `scs` (one of the RCU_CKSYSSRC_x values) as the system clock and
+// waits until the switch has happened.
+fn system_clock_switch(scs: u32) -> Result<(), ClockError> {
+    let mut cfg0 = read_register(RCU_CFG0);
+    cfg0 &= !RCU_CFG0_SCS;
+    cfg0 |= cfg0_scs(scs);
+    set_register(RCU_CFG0, cfg0);
+    wait_for(RCU_CFG0, RCU_CFG0_SCSS, scs << 2).ok_or(ClockError::SwitchTimeout)
 }
 
-fn rcu_reg_val(periph: u32) -> *mut u32 {
-    reg32(RCU + ((periph >> 6) as u32))
+// Polls until `reg & mask == value`, or returns `None` after STARTUP_TIMEOUT tries.
+fn wait_for(reg: Reg32, mask: u32, value: u32) -> Option<()> {
+    for _ in 0..STARTUP_TIMEOUT {
+        if read_register(reg) & mask == value {
+            return Some(());
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::sim;
+
+    const CTL: u32 = 0x4002_1000;
+    const CFG0: u32 = 0x4002_1004;
+    const CFG1: u32 = 0x4002_102c;
+
+    #[test]
+    fn enable_and_reset_registers() {
+        sim::reset();
+
+        rcu_periph_clock_enable(RcuPeriph::Dma1);
+        rcu_periph_clock_enable(RcuPeriph::GpioC);
+        rcu_periph_clock_enable(RcuPeriph::Dac);
+        rcu_periph_clock_disable(RcuPeriph::GpioC);
+        rcu_periph_clock_sleep_enable(RcuSleepPeriph::Fmc);
+        assert_eq!(rcu_periph_reset_pulse(RcuPeriph::Timer0), Some(()));
+        assert_eq!(rcu_periph_reset_pulse(RcuPeriph::Usbfs), Some(()));
+        assert_eq!(rcu_periph_reset_pulse(RcuPeriph::Dma0), None);
+
+        assert_eq!(
+            sim::take_writes(),
+            [
+                (0x4002_1014, 1 << 1),
+                (0x4002_1018, 1 << 4),
+                (0x4002_101c, 1 << 29),
+                (0x4002_1018, 0),
+                (0x4002_1014, (1 << 1) | (1 << 4)),
+                (0x4002_100c, 1 << 11),
+                (0x4002_100c, 0),
+                (0x4002_1028, 1 << 12),
+                (0x4002_1028, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn hxtal_pll_108mhz() {
+        sim::reset();
+        sim::preset(CTL, RCU_CTL_HXTALSTB | RCU_CTL_PLLSTB);
+        sim::preset(CFG0, RCU_CKSYSSRC_PLL << 2);
+
+        let clocks = ClockConfig::new()
+            .hxtal(8_000_000)
+            .sysclk(108_000_000)
+            .freeze()
+            .unwrap();
+
+        assert_eq!(clocks.sysclk(), 108_000_000);
+        assert_eq!(clocks.hclk(), 108_000_000);
+        assert_eq!(clocks.pclk1(), 54_000_000);
+        assert_eq!(clocks.pclk2(), 108_000_000);
+        assert_eq!(clocks.timer_apb1(), 108_000_000);
+        assert_eq!(clocks.timer_apb2(), 108_000_000);
+
+        // 8 MHz / 2 * 27, APB1 / 2, PLL selected
+        assert_eq!(sim::peek(CFG1), 1);
+        assert_eq!(
+            sim::peek(CFG0),
+            RCU_CFG0_PLLMF_4 | (10 << 18) | RCU_CFG0_PLLSEL | (0b100 << 8) | (2 << 2) | 2
+        );
+        assert_ne!(sim::peek(CTL) & RCU_CTL_HXTALEN, 0);
+        assert_ne!(sim::peek(CTL) & RCU_CTL_PLLEN, 0);
+    }
+
+    #[test]
+    fn irc8m_pll_with_divided_buses() {
+        sim::reset();
+        sim::preset(CTL, RCU_CTL_PLLSTB);
+        sim::preset(CFG0, RCU_CKSYSSRC_PLL << 2);
+
+        let clocks = ClockConfig::new()
+            .sysclk(48_000_000)
+            .hclk(24_000_000)
+            .pclk2(6_000_000)
+            .freeze()
+            .unwrap();
+
+        assert_eq!(clocks.hclk(), 24_000_000);
+        assert_eq!(clocks.pclk1(), 24_000_000);
+        assert_eq!(clocks.pclk2(), 6_000_000);
+        assert_eq!(clocks.timer_apb2(), 12_000_000);
+        // IRC8M / 2 * 12, AHB / 2, APB2 / 4
+        assert_eq!(
+            sim::peek(CFG0),
+            (10 << 18) | (0b101 << 11) | (0b1000 << 4) | (2 << 2) | 2
+        );
+        assert_eq!(sim::peek(CTL) & RCU_CTL_HXTALEN, 0);
+    }
+
+    #[test]
+    fn running_pll_is_switched_away_from_first() {
+        sim::reset();
+        sim::preset(CTL, RCU_CTL_PLLEN | RCU_CTL_PLLSTB);
+        sim::preset(CFG0, RCU_CKSYSSRC_PLL << 2 | RCU_CKSYSSRC_PLL);
+
+        // SCSS never follows in the simulation, so the switch times out
+        // before anything touches the PLL.
+        assert_eq!(
+            ClockConfig::new().sysclk(48_000_000).freeze(),
+            Err(ClockError::SwitchTimeout)
+        );
+        assert_eq!(sim::peek(CFG0) & RCU_CFG0_SCS, RCU_CKSYSSRC_IRC8M);
+        assert_eq!(sim::peek(CFG0) & RCU_CFG0_PLLMF, 0);
+        assert_ne!(sim::peek(CTL) & RCU_CTL_PLLEN, 0);
+    }
+
+    #[test]
+    fn enabled_pll_is_stopped_before_reprogramming() {
+        sim::reset();
+        sim::preset(CTL, RCU_CTL_PLLEN);
+
+        // PLLSTB never comes back, but PLLEN was cleared before PLLMF changed.
+        assert_eq!(
+            ClockConfig::new().sysclk(48_000_000).freeze(),
+            Err(ClockError::PllTimeout)
+        );
+        let writes = sim::take_writes();
+        let pll_off = writes.iter().position(|&w| w == (CTL, 0)).unwrap();
+        let pllmf = writes
+            .iter()
+            .position(|&(address, value)| address == CFG0 && value & RCU_CFG0_PLLMF != 0)
+            .unwrap();
+        assert!(pll_off < pllmf);
+    }
+
+    #[test]
+    fn errors() {
+        sim::reset();
+        assert_eq!(
+            ClockConfig::new().hxtal(8_000_000).freeze(),
+            Err(ClockError::HxtalTimeout)
+        );
+        assert_eq!(
+            ClockConfig::new().sysclk(120_000_000).freeze(),
+            Err(ClockError::SysclkTooHigh)
+        );
+        assert_eq!(
+            ClockConfig::new().sysclk(60_000_000).freeze(),
+            Err(ClockError::NoPllSetting)
+        );
+    }
 }
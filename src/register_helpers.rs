@@ -1,3 +1,6 @@
+use crate::bus::{Bus, RegisterBus};
+pub use crate::bus::{Reg32, Reg8};
+
 pub const fn bit(x: u32) -> u32 {
     (0x01 << x) as u32
 }
@@ -8,36 +11,38 @@ pub const fn bits(start: u32, end: u32) -> u32 {
     a & b
 }
 
-pub const fn reg32(addr: u32) -> *mut u32 {
-    addr as *mut u32
+pub(crate) const fn reg32(addr: u32) -> Reg32 {
+    Reg32(addr)
+}
+
+pub(crate) const fn reg8(addr: u32) -> Reg8 {
+    Reg8(addr)
+}
+
+// Sound because a Reg32/Reg8 can only come from the register maps in this
+// crate.
+pub fn read_register(register: Reg32) -> u32 {
+    unsafe { Bus::read(register) }
 }
 
-pub const fn data_align(reg_val: u32) -> u32 {
-    bits(0, 1) & (reg_val)
+pub fn set_register(register: Reg32, value: u32) {
+    unsafe { Bus::write(register, value) }
 }
 
-pub fn read_register(register: *mut u32) -> u32 {
-    unsafe { core::ptr::read_volatile(register) }
+pub fn set_bits(reg: Reg32, bits: u32) {
+    let value = read_register(reg);
+    set_register(reg, value | bits);
 }
 
-pub fn set_register(register: *mut u32, value: u32) {
-    unsafe {
-        core::ptr::write_volatile(register, value);
-    }
+pub fn reset_bits(reg: Reg32, bit: u32) {
+    let value = read_register(reg);
+    set_register(reg, value & !bit);
 }
 
-pub fn set_bits(reg: *mut u32, bits: u32) {
-    unsafe {
-        let mut value = core::ptr::read_volatile(reg);
-        value |= bits;
-        core::ptr::write_volatile(reg, value);
-    }
+pub fn read_register8(register: Reg8) -> u8 {
+    unsafe { Bus::read8(register) }
 }
 
-pub fn reset_bits(reg: *mut u32, bit: u32) {
-    unsafe {
-        let mut value = core::ptr::read_volatile(reg);
-        value &= !bit;
-        core::ptr::write_volatile(reg, value);
-    }
+pub fn set_register8(register: Reg8, value: u8) {
+    unsafe { Bus::write8(register, value) }
 }
@@ -0,0 +1,35 @@
+/* Peripheral singletons */
+// Declares the zero-sized handle type `$Name` that `take` hands out only once,
+// so whoever holds it owns the peripheral. `$owns` completes the safety note of
+// `steal`, naming everything the handle stands for.
+macro_rules! singleton {
+    ($Name:ident, $taken:ident, $owns:literal) => {
+        pub struct $Name {
+            _private: (),
+        }
+
+        static $taken: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
+
+        impl $Name {
+            // Returns the handle the first time it is called, `None` afterwards.
+            pub fn take() -> Option<$Name> {
+                if $taken.swap(true, core::sync::atomic::Ordering::SeqCst) {
+                    None
+                } else {
+                    Some($Name { _private: () })
+                }
+            }
+
+            /// Returns a handle whether or not `take` has handed one out.
+            ///
+            /// # Safety
+            ///
+            #[doc = concat!("The caller must make sure no other handle to ", $owns, " is in use.")]
+            pub unsafe fn steal() -> $Name {
+                $Name { _private: () }
+            }
+        }
+    };
+}
+
+pub(crate) use singleton;
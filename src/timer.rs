@@ -1,16 +1,18 @@
+use crate::rcu::{rcu_periph_reset_pulse, RcuPeriph};
 use crate::register_helpers::*;
+use crate::singleton::singleton;
 
 const APB1_BUS_BASE: u32 = 0x4000_0000;
 
 const TIMER_BASE: u32 = APB1_BUS_BASE + 0x0;
 
-pub(crate) const TIMER0: u32 = TIMER_BASE + 0x0001_2C00;
-pub(crate) const TIMER1: u32 = TIMER_BASE + 0x0000_0000;
-pub(crate) const TIMER2: u32 = TIMER_BASE + 0x0000_0400;
-pub(crate) const TIMER3: u32 = TIMER_BASE + 0x0000_0800;
-pub(crate) const TIMER4: u32 = TIMER_BASE + 0x0000_0C00;
-pub(crate) const TIMER5: u32 = TIMER_BASE + 0x0000_1000;
-pub(crate) const TIMER6: u32 = TIMER_BASE + 0x0000_1400;
+pub const TIMER0: u32 = TIMER_BASE + 0x0001_2C00;
+pub const TIMER1: u32 = TIMER_BASE + 0x0000_0000;
+pub const TIMER2: u32 = TIMER_BASE + 0x0000_0400;
+pub const TIMER3: u32 = TIMER_BASE + 0x0000_0800;
+pub const TIMER4: u32 = TIMER_BASE + 0x0000_0C00;
+pub const TIMER5: u32 = TIMER_BASE + 0x0000_1000;
+pub const TIMER6: u32 = TIMER_BASE + 0x0000_1400;
 
 const TIMER_CTL1_MMC: u32 = bits(4,6);
 
@@ -41,11 +43,11 @@ const fn ctl1_mmc(regval: u32) -> u32 {
     bits(4, 6) & (regval << 4)
 }
 
-const fn timer_psc(timerx: u32) -> *mut u32 {
+const fn timer_psc(timerx: u32) -> Reg32 {
     reg32(timerx + 0x28)
 }
 
-const fn timer_swevg(timerx: u32) -> *mut u32 {
+const fn timer_swevg(timerx: u32) -> Reg32 {
     reg32(timerx + 0x14)
 }
 
@@ -57,7 +59,7 @@ pub fn timer_prescaler_config(timer_periph: u32, prescaler: u16, pscreload: u32)
     }
 }
 
-fn timer_car(timerx: u32) -> *mut u32 {
+fn timer_car(timerx: u32) -> Reg32 {
     reg32(timerx + 0x2c)
 }
 
@@ -70,14 +72,1371 @@ pub fn timer_master_output_trigger_source_select(timer_periph: u32, outrigger: u
     set_bits(timer_ctl1(timer_periph), outrigger);
 }
 
-fn timer_ctl0(timerx: u32) -> *mut u32 {
+fn timer_ctl0(timerx: u32) -> Reg32 {
     reg32(timerx + 0x00)
 }
 
-fn timer_ctl1(timerx: u32) -> *mut u32 {
+fn timer_ctl1(timerx: u32) -> Reg32 {
     reg32(timerx + 0x04)
 }
 
 pub fn timer_enable(timer_periph: u32) {
     set_bits(timer_ctl0(timer_periph), TIMER_CTL0_CEN);
 }
+
+pub fn timer_disable(timer_periph: u32) {
+    reset_bits(timer_ctl0(timer_periph), TIMER_CTL0_CEN);
+}
+
+/* Counter configuration */
+const fn ctl0_cam(regval: u32) -> u32 {
+    bits(5, 6) & (regval << 5)
+}
+
+const fn ctl0_ckdiv(regval: u32) -> u32 {
+    bits(8, 9) & (regval << 8)
+}
+
+const fn timer_cnt(timerx: u32) -> Reg32 {
+    reg32(timerx + 0x24)
+}
+
+const fn timer_crep(timerx: u32) -> Reg32 {
+    reg32(timerx + 0x30)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerAlignment {
+    Edge = 0,
+    // Counts up and down; the compare interrupt flags are set while counting
+    // down, up, or both.
+    CenterDown = 1,
+    CenterUp = 2,
+    CenterBoth = 3,
+}
+
+// Only used with edge alignment; in the center-aligned modes the hardware
+// switches direction by itself.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerDirection {
+    Up,
+    Down,
+}
+
+// Ratio between the timer clock and the clock of the input filters and dead
+// time generator.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerClockDivision {
+    Div1 = 0,
+    Div2 = 1,
+    Div4 = 2,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerUpdateSource {
+    // Overflow/underflow, UPG and slave mode resets all update.
+    Global,
+    // Only overflow/underflow updates.
+    Regular,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerPulseMode {
+    Repetitive,
+    // The counter stops at the next update event.
+    Single,
+}
+
+pub struct TimerParameters {
+    // Counter clock is the timer clock / (prescaler + 1).
+    pub prescaler: u16,
+    // Counting mode, direction and clock division aren't available on the basic
+    // timers.
+    pub alignment: TimerAlignment,
+    pub direction: TimerDirection,
+    pub clock_division: TimerClockDivision,
+    // Autoreload value.
+    pub period: u16,
+    // TIMER0 only; updates happen every repetition_counter + 1 periods.
+    pub repetition_counter: u8,
+    // Buffers CAR writes until the next update event.
+    pub auto_reload_shadow: bool,
+    pub update_source: TimerUpdateSource,
+    pub pulse_mode: TimerPulseMode,
+}
+
+impl TimerParameters {
+    // Edge-aligned up counter in the reset configuration.
+    pub const fn new(prescaler: u16, period: u16) -> TimerParameters {
+        TimerParameters {
+            prescaler,
+            alignment: TimerAlignment::Edge,
+            direction: TimerDirection::Up,
+            period,
+            clock_division: TimerClockDivision::Div1,
+            repetition_counter: 0,
+            auto_reload_shadow: false,
+            update_source: TimerUpdateSource::Global,
+            pulse_mode: TimerPulseMode::Repetitive,
+        }
+    }
+}
+
+// Programs the counter and generates an update event so the prescaler and
+// repetition counter take effect at once. Leaves the counter stopped or running
+// as it was. Fails for an unknown timer, for counting options a basic timer
+// doesn't have and for a repetition counter on anything but TIMER0.
+pub fn timer_init(timer_periph: u32, init_struct: &TimerParameters) -> Option<()> {
+    let kind = timer_kind(timer_periph)?;
+    let counting = init_struct.alignment != TimerAlignment::Edge
+        || init_struct.direction != TimerDirection::Up
+        || init_struct.clock_division != TimerClockDivision::Div1;
+    if kind == TimerKind::Basic && counting {
+        return None;
+    }
+    if kind != TimerKind::Advanced && init_struct.repetition_counter != 0 {
+        return None;
+    }
+
+    set_register(timer_psc(timer_periph), init_struct.prescaler as u32);
+    set_register(timer_car(timer_periph), init_struct.period as u32);
+    if kind == TimerKind::Advanced {
+        set_register(
+            timer_crep(timer_periph),
+            init_struct.repetition_counter as u32,
+        );
+    }
+
+    let mut ctl0 = read_register(timer_ctl0(timer_periph));
+    ctl0 &= !(TIMER_CTL0_ARSE | TIMER_CTL0_UPS | TIMER_CTL0_SPM);
+    if kind != TimerKind::Basic {
+        ctl0 &= !(TIMER_CTL0_DIR | TIMER_CTL0_CAM | TIMER_CTL0_CKDIV);
+        ctl0 |= ctl0_cam(init_struct.alignment as u32);
+        ctl0 |= ctl0_ckdiv(init_struct.clock_division as u32);
+        if init_struct.direction == TimerDirection::Down {
+            ctl0 |= TIMER_CTL0_DIR;
+        }
+    }
+    if init_struct.auto_reload_shadow {
+        ctl0 |= TIMER_CTL0_ARSE;
+    }
+    if init_struct.update_source == TimerUpdateSource::Regular {
+        ctl0 |= TIMER_CTL0_UPS;
+    }
+    if init_struct.pulse_mode == TimerPulseMode::Single {
+        ctl0 |= TIMER_CTL0_SPM;
+    }
+    set_register(timer_ctl0(timer_periph), ctl0);
+
+    timer_event_software_generate(timer_periph, TIMER_EVENT_SRC_UPG);
+    Some(())
+}
+
+pub fn timer_auto_reload_shadow_enable(timer_periph: u32) {
+    set_bits(timer_ctl0(timer_periph), TIMER_CTL0_ARSE);
+}
+
+pub fn timer_auto_reload_shadow_disable(timer_periph: u32) {
+    reset_bits(timer_ctl0(timer_periph), TIMER_CTL0_ARSE);
+}
+
+// With update events disabled, the shadow registers keep their values.
+pub fn timer_update_event_enable(timer_periph: u32) {
+    reset_bits(timer_ctl0(timer_periph), TIMER_CTL0_UPDIS);
+}
+
+pub fn timer_update_event_disable(timer_periph: u32) {
+    set_bits(timer_ctl0(timer_periph), TIMER_CTL0_UPDIS);
+}
+
+pub fn timer_update_source_config(timer_periph: u32, update: TimerUpdateSource) {
+    match update {
+        TimerUpdateSource::Global => reset_bits(timer_ctl0(timer_periph), TIMER_CTL0_UPS),
+        TimerUpdateSource::Regular => set_bits(timer_ctl0(timer_periph), TIMER_CTL0_UPS),
+    }
+}
+
+pub fn timer_single_pulse_mode_config(timer_periph: u32, spmode: TimerPulseMode) {
+    match spmode {
+        TimerPulseMode::Repetitive => reset_bits(timer_ctl0(timer_periph), TIMER_CTL0_SPM),
+        TimerPulseMode::Single => set_bits(timer_ctl0(timer_periph), TIMER_CTL0_SPM),
+    }
+}
+
+pub fn timer_counter_value_config(timer_periph: u32, counter: u16) {
+    set_register(timer_cnt(timer_periph), counter as u32);
+}
+
+pub fn timer_counter_read(timer_periph: u32) -> u16 {
+    read_register(timer_cnt(timer_periph)) as u16
+}
+
+pub const TIMER_EVENT_SRC_UPG: u32 = TIMER_SWEVG_UPG;
+
+// Generates the events in `event` (TIMER_EVENT_SRC_x) as if the hardware had.
+pub fn timer_event_software_generate(timer_periph: u32, event: u32) {
+    set_register(timer_swevg(timer_periph), event);
+}
+
+pub(crate) const fn timer_rcu_periph(timer_periph: u32) -> Option<RcuPeriph> {
+    match timer_periph {
+        TIMER0 => Some(RcuPeriph::Timer0),
+        TIMER1 => Some(RcuPeriph::Timer1),
+        TIMER2 => Some(RcuPeriph::Timer2),
+        TIMER3 => Some(RcuPeriph::Timer3),
+        TIMER4 => Some(RcuPeriph::Timer4),
+        TIMER5 => Some(RcuPeriph::Timer5),
+        TIMER6 => Some(RcuPeriph::Timer6),
+        _ => None,
+    }
+}
+
+pub fn timer_deinit(timer_periph: u32) -> Option<()> {
+    rcu_periph_reset_pulse(timer_rcu_periph(timer_periph)?)
+}
+
+/* Channels */
+// TIMER0..TIMER4 have four capture/compare channels; TIMER0 also has
+// complementary outputs on CH0..CH2. Channel pins have to be switched to an
+// alternate function output (compare) or an input (capture) first.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerChannel {
+    Ch0 = 0,
+    Ch1 = 1,
+    Ch2 = 2,
+    Ch3 = 3,
+}
+
+const TIMER_CHCTL_CHXMS: u32 = bits(0, 1);
+const TIMER_CHCTL_CHXCOMSEN: u32 = bit(3);
+const TIMER_CHCTL_CHXCOMCTL: u32 = bits(4, 6);
+const TIMER_CHCTL_CHXCAPPSC: u32 = bits(2, 3);
+const TIMER_CHCTL_CHXCAPFLT: u32 = bits(4, 7);
+
+const TIMER_CHCTL2_CHXEN: u32 = bit(0);
+const TIMER_CHCTL2_CHXP: u32 = bit(1);
+const TIMER_CHCTL2_CHXNEN: u32 = bit(2);
+const TIMER_CHCTL2_CHXNP: u32 = bit(3);
+
+const TIMER_CTL1_ISOX: u32 = bit(8);
+const TIMER_CTL1_ISOXN: u32 = bit(9);
+
+const TIMER_CCHP_DTCFG: u32 = bits(0, 7);
+const TIMER_CCHP_PROT: u32 = bits(8, 9);
+const TIMER_CCHP_IOS: u32 = bit(10);
+const TIMER_CCHP_ROS: u32 = bit(11);
+const TIMER_CCHP_BRKEN: u32 = bit(12);
+const TIMER_CCHP_BRKP: u32 = bit(13);
+const TIMER_CCHP_OAEN: u32 = bit(14);
+const TIMER_CCHP_POEN: u32 = bit(15);
+
+// CH0/CH1 share CHCTL0 and CH2/CH3 share CHCTL1, a byte each.
+const fn timer_chctl(timerx: u32, channel: TimerChannel) -> Reg32 {
+    reg32(timerx + 0x18 + 4 * (channel as u32 / 2))
+}
+
+const fn chctl_shift(channel: TimerChannel) -> u32 {
+    8 * (channel as u32 % 2)
+}
+
+const fn timer_chctl2(timerx: u32) -> Reg32 {
+    reg32(timerx + 0x20)
+}
+
+const fn chctl2_shift(channel: TimerChannel) -> u32 {
+    4 * channel as u32
+}
+
+const fn timer_chcv(timerx: u32, channel: TimerChannel) -> Reg32 {
+    reg32(timerx + 0x34 + 4 * channel as u32)
+}
+
+const fn timer_cchp(timerx: u32) -> Reg32 {
+    reg32(timerx + 0x44)
+}
+
+// Channels, slave mode and most interrupts exist on TIMER0..TIMER4 only.
+fn timer_general_check(timer_periph: u32) -> Option<TimerKind> {
+    match timer_kind(timer_periph)? {
+        TimerKind::Basic => None,
+        kind => Some(kind),
+    }
+}
+
+fn timer_complementary_check(timer_periph: u32, channel: TimerChannel) -> Option<()> {
+    match (timer_kind(timer_periph)?, channel) {
+        (TimerKind::Advanced, TimerChannel::Ch0 | TimerChannel::Ch1 | TimerChannel::Ch2) => {
+            Some(())
+        }
+        _ => None,
+    }
+}
+
+fn chctl_update(timer_periph: u32, channel: TimerChannel, mask: u32, value: u32) {
+    let shift = chctl_shift(channel);
+    let reg = timer_chctl(timer_periph, channel);
+    set_register(
+        reg,
+        (read_register(reg) & !(mask << shift)) | ((value & mask) << shift),
+    );
+}
+
+// Output compare modes, OxCPRE is the reference signal before polarity.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerOcMode {
+    // Frozen, the compare only sets the flag.
+    Timing = 0,
+    // Set, cleared or toggled on a compare match.
+    Active = 1,
+    Inactive = 2,
+    Toggle = 3,
+    ForceLow = 4,
+    ForceHigh = 5,
+    // Active while the counter is below the compare value (counting up).
+    Pwm0 = 6,
+    // Inactive while the counter is below the compare value (counting up).
+    Pwm1 = 7,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerOcPolarity {
+    High,
+    Low,
+}
+
+pub struct TimerOcParameters {
+    pub output_enable: bool,
+    pub polarity: TimerOcPolarity,
+    // TIMER0 CH0..CH2 only.
+    pub complementary_enable: bool,
+    pub complementary_polarity: TimerOcPolarity,
+    // Output levels while the primary output is off, TIMER0 only.
+    pub idle_high: bool,
+    pub complementary_idle_high: bool,
+}
+
+impl TimerOcParameters {
+    // Active high output, complementary output off.
+    pub const fn new() -> TimerOcParameters {
+        TimerOcParameters {
+            output_enable: true,
+            polarity: TimerOcPolarity::High,
+            complementary_enable: false,
+            complementary_polarity: TimerOcPolarity::High,
+            idle_high: false,
+            complementary_idle_high: false,
+        }
+    }
+}
+
+impl Default for TimerOcParameters {
+    fn default() -> TimerOcParameters {
+        TimerOcParameters::new()
+    }
+}
+
+// Switches the channel to output compare and sets up its output stage. The
+// mode and compare value are configured separately.
+pub fn timer_channel_output_config(
+    timer_periph: u32,
+    channel: TimerChannel,
+    ocpara: &TimerOcParameters,
+) -> Option<()> {
+    let kind = timer_general_check(timer_periph)?;
+    if ocpara.complementary_enable {
+        timer_complementary_check(timer_periph, channel)?;
+    }
+
+    let shift = chctl2_shift(channel);
+    reset_bits(timer_chctl2(timer_periph), TIMER_CHCTL2_CHXEN << shift);
+    chctl_update(timer_periph, channel, TIMER_CHCTL_CHXMS, 0);
+
+    let mut chctl2 = read_register(timer_chctl2(timer_periph));
+    chctl2 &= !((TIMER_CHCTL2_CHXP | TIMER_CHCTL2_CHXNEN | TIMER_CHCTL2_CHXNP) << shift);
+    if ocpara.polarity == TimerOcPolarity::Low {
+        chctl2 |= TIMER_CHCTL2_CHXP << shift;
+    }
+    if ocpara.complementary_enable {
+        chctl2 |= TIMER_CHCTL2_CHXNEN << shift;
+    }
+    if ocpara.complementary_polarity == TimerOcPolarity::Low
+        && timer_complementary_check(timer_periph, channel).is_some()
+    {
+        chctl2 |= TIMER_CHCTL2_CHXNP << shift;
+    }
+    if ocpara.output_enable {
+        chctl2 |= TIMER_CHCTL2_CHXEN << shift;
+    }
+    set_register(timer_chctl2(timer_periph), chctl2);
+
+    if kind == TimerKind::Advanced {
+        let iso_shift = 2 * channel as u32;
+        let mut ctl1 = read_register(timer_ctl1(timer_periph));
+        ctl1 &= !((TIMER_CTL1_ISOX | TIMER_CTL1_ISOXN) << iso_shift);
+        if ocpara.idle_high {
+            ctl1 |= TIMER_CTL1_ISOX << iso_shift;
+        }
+        if ocpara.complementary_idle_high && channel != TimerChannel::Ch3 {
+            ctl1 |= TIMER_CTL1_ISOXN << iso_shift;
+        }
+        set_register(timer_ctl1(timer_periph), ctl1);
+    }
+    Some(())
+}
+
+pub fn timer_channel_output_mode_config(
+    timer_periph: u32,
+    channel: TimerChannel,
+    ocmode: TimerOcMode,
+) -> Option<()> {
+    timer_general_check(timer_periph)?;
+    chctl_update(
+        timer_periph,
+        channel,
+        TIMER_CHCTL_CHXCOMCTL,
+        (ocmode as u32) << 4,
+    );
+    Some(())
+}
+
+pub fn timer_channel_output_pulse_value_config(
+    timer_periph: u32,
+    channel: TimerChannel,
+    pulse: u16,
+) -> Option<()> {
+    timer_general_check(timer_periph)?;
+    set_register(timer_chcv(timer_periph, channel), pulse as u32);
+    Some(())
+}
+
+// Sets the compare value to `duty_permille` of the current period, for the
+// PWM modes counting up. 1000 keeps the output active for the whole period
+// unless the autoreload value is 0xffff.
+pub fn timer_channel_output_duty_config(
+    timer_periph: u32,
+    channel: TimerChannel,
+    duty_permille: u16,
+) -> Option<()> {
+    if duty_permille > 1000 {
+        return None;
+    }
+    let period = read_register(timer_car(timer_periph)) + 1;
+    let pulse = (period * duty_permille as u32 / 1000).min(0xffff);
+    timer_channel_output_pulse_value_config(timer_periph, channel, pulse as u16)
+}
+
+// Buffers compare value writes until the next update event, so a duty
+// change never produces a glitched period.
+pub fn timer_channel_output_shadow_enable(timer_periph: u32, channel: TimerChannel) -> Option<()> {
+    timer_general_check(timer_periph)?;
+    set_bits(
+        timer_chctl(timer_periph, channel),
+        TIMER_CHCTL_CHXCOMSEN << chctl_shift(channel),
+    );
+    Some(())
+}
+
+pub fn timer_channel_output_shadow_disable(timer_periph: u32, channel: TimerChannel) -> Option<()> {
+    timer_general_check(timer_periph)?;
+    reset_bits(
+        timer_chctl(timer_periph, channel),
+        TIMER_CHCTL_CHXCOMSEN << chctl_shift(channel),
+    );
+    Some(())
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerIcPolarity {
+    Rising,
+    Falling,
+    BothEdge,
+}
+
+// Which input feeds the capture: the channel's own pin, the pin of the
+// neighbouring channel (CH0<->CH1, CH2<->CH3) or the slave mode trigger.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerIcSelection {
+    DirectTi = 1,
+    IndirectTi = 2,
+    Itc = 3,
+}
+
+// Captures on every, every 2nd, 4th or 8th edge.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerIcPrescaler {
+    Div1 = 0,
+    Div2 = 1,
+    Div4 = 2,
+    Div8 = 3,
+}
+
+pub struct TimerIcParameters {
+    pub polarity: TimerIcPolarity,
+    pub selection: TimerIcSelection,
+    pub prescaler: TimerIcPrescaler,
+    // Digital filter setting 0..=15, 0 samples without filtering.
+    pub filter: u8,
+}
+
+pub fn timer_input_capture_config(
+    timer_periph: u32,
+    channel: TimerChannel,
+    icpara: &TimerIcParameters,
+) -> Option<()> {
+    timer_general_check(timer_periph)?;
+    if icpara.filter > 0xf {
+        return None;
+    }
+
+    // CHxMS is only writable while the channel is off.
+    let shift = chctl2_shift(channel);
+    reset_bits(timer_chctl2(timer_periph), TIMER_CHCTL2_CHXEN << shift);
+
+    let chctl =
+        icpara.selection as u32 | (icpara.prescaler as u32) << 2 | (icpara.filter as u32) << 4;
+    chctl_update(
+        timer_periph,
+        channel,
+        TIMER_CHCTL_CHXMS | TIMER_CHCTL_CHXCAPPSC | TIMER_CHCTL_CHXCAPFLT,
+        chctl,
+    );
+
+    let polarity = match icpara.polarity {
+        TimerIcPolarity::Rising => 0,
+        TimerIcPolarity::Falling => TIMER_CHCTL2_CHXP,
+        TimerIcPolarity::BothEdge => TIMER_CHCTL2_CHXP | TIMER_CHCTL2_CHXNP,
+    };
+    let mut chctl2 = read_register(timer_chctl2(timer_periph));
+    chctl2 &= !((TIMER_CHCTL2_CHXP | TIMER_CHCTL2_CHXNP) << shift);
+    chctl2 |= (polarity | TIMER_CHCTL2_CHXEN) << shift;
+    set_register(timer_chctl2(timer_periph), chctl2);
+    Some(())
+}
+
+pub fn timer_channel_capture_value_register_read(
+    timer_periph: u32,
+    channel: TimerChannel,
+) -> Option<u16> {
+    timer_general_check(timer_periph)?;
+    Some(read_register(timer_chcv(timer_periph, channel)) as u16)
+}
+
+/* Complementary outputs (TIMER0) */
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerBreakPolarity {
+    Low,
+    High,
+}
+
+// Locks parts of the break, dead time and channel configuration until the
+// next reset, level 2 being the most restrictive.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerProtectMode {
+    Off = 0,
+    Level0 = 1,
+    Level1 = 2,
+    Level2 = 3,
+}
+
+pub struct TimerBreakParameters {
+    // Drive the idle levels instead of releasing the pins while the outputs
+    // are disabled, with the primary output on (run) or off (idle).
+    pub run_off_state: bool,
+    pub idle_off_state: bool,
+    // Raw DTCFG value, see timer_dead_time_encode.
+    pub dead_time: u8,
+    pub break_enable: bool,
+    pub break_polarity: TimerBreakPolarity,
+    // Turns the primary output back on at the next update after a break.
+    pub output_auto_enable: bool,
+    pub protect_mode: TimerProtectMode,
+}
+
+// Encodes a dead time of `ticks` dead time generator clocks (timer clock /
+// clock division) into DTCFG, rounding down. Fails above 1008 clocks.
+pub const fn timer_dead_time_encode(ticks: u32) -> Option<u8> {
+    match ticks {
+        0..=127 => Some(ticks as u8),
+        128..=255 => Some(0x80 | (ticks / 2 - 64) as u8),
+        256..=511 => Some(0xc0 | (ticks / 8 - 32) as u8),
+        512..=1008 => Some(0xe0 | (ticks / 16 - 32) as u8),
+        _ => None,
+    }
+}
+
+// Writes the whole CCHP register, keeping the primary output state. Can only
+// be done once when the protect mode is not Off.
+pub fn timer_break_config(timer_periph: u32, breakpara: &TimerBreakParameters) -> Option<()> {
+    if timer_kind(timer_periph)? != TimerKind::Advanced {
+        return None;
+    }
+
+    let mut cchp = read_register(timer_cchp(timer_periph)) & TIMER_CCHP_POEN;
+    cchp |= breakpara.dead_time as u32 & TIMER_CCHP_DTCFG;
+    cchp |= ((breakpara.protect_mode as u32) << 8) & TIMER_CCHP_PROT;
+    if breakpara.idle_off_state {
+        cchp |= TIMER_CCHP_IOS;
+    }
+    if breakpara.run_off_state {
+        cchp |= TIMER_CCHP_ROS;
+    }
+    if breakpara.break_enable {
+        cchp |= TIMER_CCHP_BRKEN;
+    }
+    if breakpara.break_polarity == TimerBreakPolarity::High {
+        cchp |= TIMER_CCHP_BRKP;
+    }
+    if breakpara.output_auto_enable {
+        cchp |= TIMER_CCHP_OAEN;
+    }
+    set_register(timer_cchp(timer_periph), cchp);
+    Some(())
+}
+
+// The advanced timer's channel outputs stay off until the primary output is
+// enabled; a break clears it again.
+pub fn timer_primary_output_enable(timer_periph: u32) -> Option<()> {
+    if timer_kind(timer_periph)? != TimerKind::Advanced {
+        return None;
+    }
+    set_bits(timer_cchp(timer_periph), TIMER_CCHP_POEN);
+    Some(())
+}
+
+pub fn timer_primary_output_disable(timer_periph: u32) -> Option<()> {
+    if timer_kind(timer_periph)? != TimerKind::Advanced {
+        return None;
+    }
+    reset_bits(timer_cchp(timer_periph), TIMER_CCHP_POEN);
+    Some(())
+}
+
+/* Slave mode */
+const TIMER_SMCFG_SMC: u32 = bits(0, 2);
+const TIMER_SMCFG_TRGS: u32 = bits(4, 6);
+const TIMER_SMCFG_MSM: u32 = bit(7);
+
+const fn timer_smcfg(timerx: u32) -> Reg32 {
+    reg32(timerx + 0x08)
+}
+
+// What the slave controller does on its trigger input (TRGI).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerSlaveMode {
+    // The prescaler is clocked by the internal timer clock.
+    Disable = 0,
+    // A rising TRGI edge restarts the counter and generates an update.
+    Restart = 4,
+    // The counter runs only while TRGI is high.
+    Pause = 5,
+    // A rising TRGI edge starts the counter.
+    Event = 6,
+    // Rising TRGI edges clock the counter.
+    ExternalClock = 7,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerTriggerSource {
+    // TRGO of another timer, see timer_internal_trigger.
+    Iti0 = 0,
+    Iti1 = 1,
+    Iti2 = 2,
+    Iti3 = 3,
+    // CI0 edge detector, filtered CI0 and CI1, filtered external trigger.
+    Ci0fEd = 4,
+    Ci0fe0 = 5,
+    Ci1fe1 = 6,
+    Etifp = 7,
+}
+
+// Internal trigger inputs of each slave capable timer, indexed by ITI number.
+// TIMER5 and TIMER6 can be masters but have no slave controller.
+const TIMER_ITI_MASTERS: [(u32, [Option<u32>; 4]); 5] = [
+    (
+        TIMER0,
+        [Some(TIMER4), Some(TIMER1), Some(TIMER2), Some(TIMER3)],
+    ),
+    (TIMER1, [Some(TIMER0), None, Some(TIMER2), Some(TIMER3)]),
+    (
+        TIMER2,
+        [Some(TIMER0), Some(TIMER1), Some(TIMER4), Some(TIMER3)],
+    ),
+    (TIMER3, [Some(TIMER0), Some(TIMER1), Some(TIMER2), None]),
+    (TIMER4, [Some(TIMER1), Some(TIMER2), Some(TIMER3), None]),
+];
+
+// Returns the ITI input of `slave_periph` that carries the TRGO of
+// `master_periph`, if the two are connected.
+pub fn timer_internal_trigger(master_periph: u32, slave_periph: u32) -> Option<TimerTriggerSource> {
+    let (_, masters) = TIMER_ITI_MASTERS
+        .iter()
+        .find(|(slave, _)| *slave == slave_periph)?;
+    let iti = masters
+        .iter()
+        .position(|master| *master == Some(master_periph))?;
+    Some(
+        [
+            TimerTriggerSource::Iti0,
+            TimerTriggerSource::Iti1,
+            TimerTriggerSource::Iti2,
+            TimerTriggerSource::Iti3,
+        ][iti],
+    )
+}
+
+// Selects TRGI and the slave mode. TRGS is changed with the slave controller
+// disabled so no spurious trigger edge is seen.
+pub fn timer_slave_mode_config(
+    timer_periph: u32,
+    slavemode: TimerSlaveMode,
+    intrigger: TimerTriggerSource,
+) -> Option<()> {
+    timer_general_check(timer_periph)?;
+    reset_bits(timer_smcfg(timer_periph), TIMER_SMCFG_SMC);
+    let smcfg = read_register(timer_smcfg(timer_periph)) & !TIMER_SMCFG_TRGS;
+    set_register(timer_smcfg(timer_periph), smcfg | (intrigger as u32) << 4);
+    set_bits(timer_smcfg(timer_periph), slavemode as u32);
+    Some(())
+}
+
+// Makes `slave_periph` follow the TRGO of `master_periph`, whose trigger
+// output has to be selected with timer_master_output_trigger_source_select.
+pub fn timer_slave_chain_config(
+    slave_periph: u32,
+    master_periph: u32,
+    slavemode: TimerSlaveMode,
+) -> Option<()> {
+    let intrigger = timer_internal_trigger(master_periph, slave_periph)?;
+    timer_slave_mode_config(slave_periph, slavemode, intrigger)
+}
+
+// Delays the trigger input so a master and its slaves started by the same
+// event stay in step.
+pub fn timer_master_slave_mode_enable(timer_periph: u32) -> Option<()> {
+    timer_general_check(timer_periph)?;
+    set_bits(timer_smcfg(timer_periph), TIMER_SMCFG_MSM);
+    Some(())
+}
+
+pub fn timer_master_slave_mode_disable(timer_periph: u32) -> Option<()> {
+    timer_general_check(timer_periph)?;
+    reset_bits(timer_smcfg(timer_periph), TIMER_SMCFG_MSM);
+    Some(())
+}
+
+/* Interrupts and DMA requests */
+pub const TIMER_INT_UP: u32 = bit(0);
+pub const TIMER_INT_CH0: u32 = bit(1);
+pub const TIMER_INT_CH1: u32 = bit(2);
+pub const TIMER_INT_CH2: u32 = bit(3);
+pub const TIMER_INT_CH3: u32 = bit(4);
+pub const TIMER_INT_CMT: u32 = bit(5);
+pub const TIMER_INT_TRG: u32 = bit(6);
+pub const TIMER_INT_BRK: u32 = bit(7);
+
+pub const TIMER_DMA_UPD: u32 = bit(8);
+pub const TIMER_DMA_CH0D: u32 = bit(9);
+pub const TIMER_DMA_CH1D: u32 = bit(10);
+pub const TIMER_DMA_CH2D: u32 = bit(11);
+pub const TIMER_DMA_CH3D: u32 = bit(12);
+pub const TIMER_DMA_CMTD: u32 = bit(13);
+pub const TIMER_DMA_TRGD: u32 = bit(14);
+
+// INTF has the interrupt flags at the same positions as the enables, plus the
+// capture overcapture flags.
+pub const TIMER_FLAG_UP: u32 = TIMER_INT_UP;
+pub const TIMER_FLAG_CH0: u32 = TIMER_INT_CH0;
+pub const TIMER_FLAG_CH1: u32 = TIMER_INT_CH1;
+pub const TIMER_FLAG_CH2: u32 = TIMER_INT_CH2;
+pub const TIMER_FLAG_CH3: u32 = TIMER_INT_CH3;
+pub const TIMER_FLAG_CMT: u32 = TIMER_INT_CMT;
+pub const TIMER_FLAG_TRG: u32 = TIMER_INT_TRG;
+pub const TIMER_FLAG_BRK: u32 = TIMER_INT_BRK;
+pub const TIMER_FLAG_CH0O: u32 = bit(9);
+pub const TIMER_FLAG_CH1O: u32 = bit(10);
+pub const TIMER_FLAG_CH2O: u32 = bit(11);
+pub const TIMER_FLAG_CH3O: u32 = bit(12);
+
+// DMATA values: the first register of a burst, in words from CTL0.
+pub const TIMER_DMACFG_DMATA_CTL0: u32 = 0;
+pub const TIMER_DMACFG_DMATA_CNT: u32 = 9;
+pub const TIMER_DMACFG_DMATA_PSC: u32 = 10;
+pub const TIMER_DMACFG_DMATA_CAR: u32 = 11;
+pub const TIMER_DMACFG_DMATA_CREP: u32 = 12;
+pub const TIMER_DMACFG_DMATA_CH0CV: u32 = 13;
+pub const TIMER_DMACFG_DMATA_CH3CV: u32 = 16;
+pub const TIMER_DMACFG_DMATA_CCHP: u32 = 17;
+
+const TIMER_DMACFG_DMATA: u32 = bits(0, 4);
+const TIMER_DMACFG_DMATC: u32 = bits(8, 12);
+
+const fn timer_dmainten(timerx: u32) -> Reg32 {
+    reg32(timerx + 0x0c)
+}
+
+const fn timer_intf(timerx: u32) -> Reg32 {
+    reg32(timerx + 0x10)
+}
+
+const fn timer_dmacfg(timerx: u32) -> Reg32 {
+    reg32(timerx + 0x48)
+}
+
+const fn timer_dmatb(timerx: u32) -> u32 {
+    timerx + 0x4c
+}
+
+// DMAINTEN bits and INTF flags `timer_periph` implements.
+const fn timer_dmainten_mask(kind: TimerKind) -> u32 {
+    match kind {
+        TimerKind::Advanced => bits(0, 14),
+        TimerKind::GeneralPurpose => bits(0, 4) | TIMER_INT_TRG | bits(8, 12) | TIMER_DMA_TRGD,
+        TimerKind::Basic => TIMER_INT_UP | TIMER_DMA_UPD,
+    }
+}
+
+const fn timer_intf_mask(kind: TimerKind) -> u32 {
+    match kind {
+        TimerKind::Advanced => bits(0, 7) | bits(9, 12),
+        TimerKind::GeneralPurpose => bits(0, 4) | TIMER_FLAG_TRG | bits(9, 12),
+        TimerKind::Basic => TIMER_FLAG_UP,
+    }
+}
+
+fn timer_dmainten_check(timer_periph: u32, value: u32) -> Option<()> {
+    let mask = timer_dmainten_mask(timer_kind(timer_periph)?);
+    if value & !mask != 0 {
+        return None;
+    }
+    Some(())
+}
+
+fn timer_intf_check(timer_periph: u32, flag: u32) -> Option<()> {
+    let mask = timer_intf_mask(timer_kind(timer_periph)?);
+    if flag & !mask != 0 {
+        return None;
+    }
+    Some(())
+}
+
+// `interrupt` is a combination of TIMER_INT_x; fails if the timer lacks one.
+pub fn timer_interrupt_enable(timer_periph: u32, interrupt: u32) -> Option<()> {
+    timer_dmainten_check(timer_periph, interrupt & bits(0, 7))?;
+    set_bits(timer_dmainten(timer_periph), interrupt & bits(0, 7));
+    Some(())
+}
+
+pub fn timer_interrupt_disable(timer_periph: u32, interrupt: u32) -> Option<()> {
+    timer_dmainten_check(timer_periph, interrupt & bits(0, 7))?;
+    reset_bits(timer_dmainten(timer_periph), interrupt & bits(0, 7));
+    Some(())
+}
+
+// `dma` is a combination of TIMER_DMA_x.
+pub fn timer_dma_enable(timer_periph: u32, dma: u32) -> Option<()> {
+    timer_dmainten_check(timer_periph, dma & bits(8, 14))?;
+    set_bits(timer_dmainten(timer_periph), dma & bits(8, 14));
+    Some(())
+}
+
+pub fn timer_dma_disable(timer_periph: u32, dma: u32) -> Option<()> {
+    timer_dmainten_check(timer_periph, dma & bits(8, 14))?;
+    reset_bits(timer_dmainten(timer_periph), dma & bits(8, 14));
+    Some(())
+}
+
+// True if any of the TIMER_FLAG_x in `flag` is set, whether its interrupt is
+// enabled or not.
+pub fn timer_flag_get(timer_periph: u32, flag: u32) -> bool {
+    read_register(timer_intf(timer_periph)) & flag != 0
+}
+
+// INTF bits are cleared by writing 0 and unaffected by writing 1, so this
+// doesn't race with flags the hardware sets meanwhile.
+pub fn timer_flag_clear(timer_periph: u32, flag: u32) -> Option<()> {
+    timer_intf_check(timer_periph, flag)?;
+    set_register(
+        timer_intf(timer_periph),
+        !flag & timer_intf_mask(timer_kind(timer_periph)?),
+    );
+    Some(())
+}
+
+// True if the flag of any of the TIMER_INT_x in `interrupt` is set and that
+// interrupt is enabled.
+pub fn timer_interrupt_flag_get(timer_periph: u32, interrupt: u32) -> bool {
+    let enabled = read_register(timer_dmainten(timer_periph)) & interrupt & bits(0, 7);
+    read_register(timer_intf(timer_periph)) & enabled != 0
+}
+
+pub fn timer_interrupt_flag_clear(timer_periph: u32, interrupt: u32) -> Option<()> {
+    timer_flag_clear(timer_periph, interrupt & bits(0, 7))
+}
+
+// A DMA request of the timer then moves `length` (1..=18) words between
+// memory and consecutive registers starting at `dma_baseaddr`
+// (TIMER_DMACFG_DMATA_x), through the DMATB register at
+// timer_dma_burst_address. Lets a single update request rewrite e.g. PSC and
+// CAR together.
+pub fn timer_dma_transfer_config(timer_periph: u32, dma_baseaddr: u32, length: u32) -> Option<()> {
+    timer_general_check(timer_periph)?;
+    if !(1..=18).contains(&length) || dma_baseaddr + length > 19 {
+        return None;
+    }
+    let dmacfg = (dma_baseaddr & TIMER_DMACFG_DMATA) | (((length - 1) << 8) & TIMER_DMACFG_DMATC);
+    set_register(timer_dmacfg(timer_periph), dmacfg);
+    Some(())
+}
+
+// Peripheral address for DMA bursts, see timer_dma_transfer_config.
+pub const fn timer_dma_burst_address(timer_periph: u32) -> u32 {
+    timer_dmatb(timer_periph)
+}
+
+// Address of the autoreload register, for single-word DMA updates. Note that
+// TIMER5_UP shares DMA1 channel 2 with DAC0.
+pub const fn timer_autoreload_address(timer_periph: u32) -> u32 {
+    timer_periph + 0x2c
+}
+
+/* Timer handles */
+// TIMER0 is the advanced timer (complementary outputs, dead time, break),
+// TIMER1..TIMER4 are general purpose (four capture/compare channels, slave
+// mode) and TIMER5/TIMER6 are basic timers that can only count and emit
+// TRGO, e.g. to trigger the DAC.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimerKind {
+    Advanced,
+    GeneralPurpose,
+    Basic,
+}
+
+pub const fn timer_kind(timer_periph: u32) -> Option<TimerKind> {
+    match timer_periph {
+        TIMER0 => Some(TimerKind::Advanced),
+        TIMER1 | TIMER2 | TIMER3 | TIMER4 => Some(TimerKind::GeneralPurpose),
+        TIMER5 | TIMER6 => Some(TimerKind::Basic),
+        _ => None,
+    }
+}
+
+// Ties a timer handle to its registers.
+pub trait TimerPeriph {
+    const PERIPH: u32;
+    const KIND: TimerKind;
+    const RCU: RcuPeriph;
+}
+
+macro_rules! timer_handle {
+    ($Timerx:ident, $taken:ident, $periph:expr, $kind:ident, $rcu:ident) => {
+        singleton!($Timerx, $taken, "this timer");
+
+        impl $Timerx {
+            // Base address, for the `timer_*` functions.
+            pub const fn periph(&self) -> u32 {
+                $periph
+            }
+        }
+
+        impl TimerPeriph for $Timerx {
+            const PERIPH: u32 = $periph;
+            const KIND: TimerKind = TimerKind::$kind;
+            const RCU: RcuPeriph = RcuPeriph::$rcu;
+        }
+    };
+}
+
+timer_handle!(Timer0, TIMER0_TAKEN, TIMER0, Advanced, Timer0);
+timer_handle!(Timer1, TIMER1_TAKEN, TIMER1, GeneralPurpose, Timer1);
+timer_handle!(Timer2, TIMER2_TAKEN, TIMER2, GeneralPurpose, Timer2);
+timer_handle!(Timer3, TIMER3_TAKEN, TIMER3, GeneralPurpose, Timer3);
+timer_handle!(Timer4, TIMER4_TAKEN, TIMER4, GeneralPurpose, Timer4);
+timer_handle!(Timer5, TIMER5_TAKEN, TIMER5, Basic, Timer5);
+timer_handle!(Timer6, TIMER6_TAKEN, TIMER6, Basic, Timer6);
+
+/* Sample player */
+// Hands out one sample of `samples` per update event, from the timer's
+// interrupt handler, e.g. to drive the DAC in software while its DMA channel is
+// busy. Configure the timer for the sample rate first.
+pub struct SamplePlayer<T: TimerPeriph> {
+    timer: T,
+    samples: &'static [u16],
+    index: usize,
+}
+
+impl<T: TimerPeriph> SamplePlayer<T> {
+    // Enables the update interrupt and starts the counter. Fails for an empty
+    // table.
+    pub fn start(timer: T, samples: &'static [u16]) -> Option<SamplePlayer<T>> {
+        if samples.is_empty() {
+            return None;
+        }
+        timer_interrupt_flag_clear(T::PERIPH, TIMER_INT_UP)?;
+        timer_interrupt_enable(T::PERIPH, TIMER_INT_UP)?;
+        timer_enable(T::PERIPH);
+        Some(SamplePlayer {
+            timer,
+            samples,
+            index: 0,
+        })
+    }
+
+    // Stops the counter and the interrupt and gives back the timer.
+    pub fn stop(self) -> T {
+        timer_disable(T::PERIPH);
+        timer_interrupt_disable(T::PERIPH, TIMER_INT_UP);
+        self.timer
+    }
+
+    // Call from the timer's interrupt handler, e.g.
+    // `player.on_interrupt(|sample| dac0.data_set(DacAlign::Right12, sample))`.
+    pub fn on_interrupt<F: FnMut(u16)>(&mut self, mut write: F) {
+        if timer_interrupt_flag_get(T::PERIPH, TIMER_INT_UP) {
+            timer_interrupt_flag_clear(T::PERIPH, TIMER_INT_UP);
+            write(self.samples[self.index]);
+            self.index = (self.index + 1) % self.samples.len();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::sim;
+
+    const TIMER0_CTL0: u32 = 0x4001_2c00;
+    const TIMER0_SWEVG: u32 = 0x4001_2c14;
+    const TIMER0_CREP: u32 = 0x4001_2c30;
+    const TIMER2_CTL0: u32 = 0x4000_0400;
+    const TIMER2_CNT: u32 = 0x4000_0424;
+    const TIMER2_PSC: u32 = 0x4000_0428;
+    const TIMER2_CAR: u32 = 0x4000_042c;
+    const TIMER6_CTL0: u32 = 0x4000_1400;
+
+    #[test]
+    fn init_programs_counter_mode() {
+        sim::reset();
+        sim::preset(TIMER2_CTL0, TIMER_CTL0_CEN | TIMER_CTL0_UPS);
+        let mut parameters = TimerParameters::new(107, 999);
+        parameters.alignment = TimerAlignment::CenterBoth;
+        parameters.clock_division = TimerClockDivision::Div4;
+        parameters.auto_reload_shadow = true;
+        parameters.pulse_mode = TimerPulseMode::Single;
+
+        assert_eq!(timer_init(TIMER2, &parameters), Some(()));
+
+        assert_eq!(sim::peek(TIMER2_PSC), 107);
+        assert_eq!(sim::peek(TIMER2_CAR), 999);
+        // CKDIV /4, ARSE, center-aligned both, SPM, still enabled
+        assert_eq!(sim::peek(TIMER2_CTL0), 0x2e9);
+    }
+
+    #[test]
+    fn init_checks_timer_capabilities() {
+        sim::reset();
+        let mut parameters = TimerParameters::new(0, 99);
+        parameters.direction = TimerDirection::Down;
+        assert_eq!(timer_init(TIMER6, &parameters), None);
+
+        let mut parameters = TimerParameters::new(0, 99);
+        parameters.repetition_counter = 3;
+        assert_eq!(timer_init(TIMER6, &parameters), None);
+        assert_eq!(sim::take_writes(), []);
+
+        assert_eq!(timer_init(TIMER0, &parameters), Some(()));
+        assert_eq!(sim::peek(TIMER0_CREP), 3);
+        assert_eq!(sim::peek(TIMER0_SWEVG), TIMER_EVENT_SRC_UPG);
+        assert_eq!(sim::peek(TIMER0_CTL0), 0);
+
+        parameters.repetition_counter = 0;
+        parameters.update_source = TimerUpdateSource::Regular;
+        assert_eq!(timer_init(TIMER6, &parameters), Some(()));
+        assert_eq!(sim::peek(TIMER6_CTL0), TIMER_CTL0_UPS);
+    }
+
+    #[test]
+    fn counter_and_update_control() {
+        sim::reset();
+        sim::preset(TIMER2_CTL0, TIMER_CTL0_CEN);
+
+        timer_update_event_disable(TIMER2);
+        timer_auto_reload_shadow_enable(TIMER2);
+        assert_eq!(sim::peek(TIMER2_CTL0), 0x83);
+        timer_update_event_enable(TIMER2);
+        timer_disable(TIMER2);
+        assert_eq!(sim::peek(TIMER2_CTL0), 0x80);
+
+        timer_counter_value_config(TIMER2, 1234);
+        assert_eq!(sim::peek(TIMER2_CNT), 1234);
+        assert_eq!(timer_counter_read(TIMER2), 1234);
+    }
+
+    const TIMER0_CTL1: u32 = 0x4001_2c04;
+    const TIMER0_CHCTL2: u32 = 0x4001_2c20;
+    const TIMER0_CCHP: u32 = 0x4001_2c44;
+    const TIMER1_CHCTL1: u32 = 0x4000_001c;
+    const TIMER1_CHCTL2: u32 = 0x4000_0020;
+    const TIMER1_CH2CV: u32 = 0x4000_003c;
+    const TIMER2_CHCTL0: u32 = 0x4000_0418;
+    const TIMER2_CHCTL2: u32 = 0x4000_0420;
+    const TIMER2_CH1CV: u32 = 0x4000_0438;
+
+    #[test]
+    fn pwm_output_on_channel_1() {
+        sim::reset();
+        sim::preset(TIMER2_CAR, 399);
+        sim::preset(TIMER2_CHCTL0, 0x0068);
+        let mut ocpara = TimerOcParameters::new();
+        ocpara.polarity = TimerOcPolarity::Low;
+
+        assert_eq!(
+            timer_channel_output_config(TIMER2, TimerChannel::Ch1, &ocpara),
+            Some(())
+        );
+        assert_eq!(
+            timer_channel_output_mode_config(TIMER2, TimerChannel::Ch1, TimerOcMode::Pwm0),
+            Some(())
+        );
+        assert_eq!(
+            timer_channel_output_shadow_enable(TIMER2, TimerChannel::Ch1),
+            Some(())
+        );
+        assert_eq!(
+            timer_channel_output_duty_config(TIMER2, TimerChannel::Ch1, 250),
+            Some(())
+        );
+
+        // CH0 untouched, CH1 PWM0 with shadowing
+        assert_eq!(sim::peek(TIMER2_CHCTL0), 0x6868);
+        assert_eq!(sim::peek(TIMER2_CHCTL2), 0x30);
+        assert_eq!(sim::peek(TIMER2_CH1CV), 100);
+
+        assert_eq!(
+            timer_channel_output_duty_config(TIMER2, TimerChannel::Ch1, 1001),
+            None
+        );
+        ocpara.complementary_enable = true;
+        assert_eq!(
+            timer_channel_output_config(TIMER2, TimerChannel::Ch1, &ocpara),
+            None
+        );
+        assert_eq!(
+            timer_channel_output_mode_config(TIMER5, TimerChannel::Ch0, TimerOcMode::Pwm0),
+            None
+        );
+    }
+
+    #[test]
+    fn input_capture_on_channel_2() {
+        sim::reset();
+        sim::preset(TIMER1_CH2CV, 0x1234);
+        let icpara = TimerIcParameters {
+            polarity: TimerIcPolarity::BothEdge,
+            selection: TimerIcSelection::DirectTi,
+            prescaler: TimerIcPrescaler::Div4,
+            filter: 3,
+        };
+
+        assert_eq!(
+            timer_input_capture_config(TIMER1, TimerChannel::Ch2, &icpara),
+            Some(())
+        );
+        assert_eq!(sim::peek(TIMER1_CHCTL1), 0x39);
+        assert_eq!(sim::peek(TIMER1_CHCTL2), 0xb00);
+        assert_eq!(
+            timer_channel_capture_value_register_read(TIMER1, TimerChannel::Ch2),
+            Some(0x1234)
+        );
+
+        let icpara = TimerIcParameters {
+            filter: 16,
+            ..icpara
+        };
+        assert_eq!(
+            timer_input_capture_config(TIMER1, TimerChannel::Ch2, &icpara),
+            None
+        );
+    }
+
+    #[test]
+    fn complementary_outputs_with_dead_time() {
+        sim::reset();
+        let ocpara = TimerOcParameters {
+            complementary_enable: true,
+            complementary_polarity: TimerOcPolarity::Low,
+            idle_high: true,
+            ..TimerOcParameters::new()
+        };
+        assert_eq!(
+            timer_channel_output_config(TIMER0, TimerChannel::Ch1, &ocpara),
+            Some(())
+        );
+        assert_eq!(sim::peek(TIMER0_CHCTL2), 0xd0);
+        assert_eq!(sim::peek(TIMER0_CTL1), 0x400);
+        assert_eq!(
+            timer_channel_output_config(TIMER0, TimerChannel::Ch3, &ocpara),
+            None
+        );
+
+        let breakpara = TimerBreakParameters {
+            run_off_state: true,
+            idle_off_state: false,
+            dead_time: timer_dead_time_encode(200).unwrap(),
+            break_enable: true,
+            break_polarity: TimerBreakPolarity::Low,
+            output_auto_enable: false,
+            protect_mode: TimerProtectMode::Off,
+        };
+        assert_eq!(timer_primary_output_enable(TIMER0), Some(()));
+        assert_eq!(timer_break_config(TIMER0, &breakpara), Some(()));
+        assert_eq!(sim::peek(TIMER0_CCHP), 0x98a4);
+        assert_eq!(timer_break_config(TIMER2, &breakpara), None);
+    }
+
+    #[test]
+    fn dead_time_encoding() {
+        assert_eq!(timer_dead_time_encode(100), Some(100));
+        assert_eq!(timer_dead_time_encode(129), Some(0x80));
+        assert_eq!(timer_dead_time_encode(300), Some(0xc5));
+        assert_eq!(timer_dead_time_encode(1008), Some(0xff));
+        assert_eq!(timer_dead_time_encode(1009), None);
+    }
+
+    const TIMER1_SMCFG: u32 = 0x4000_0008;
+
+    #[test]
+    fn internal_trigger_routing() {
+        assert_eq!(
+            timer_internal_trigger(TIMER4, TIMER0),
+            Some(TimerTriggerSource::Iti0)
+        );
+        assert_eq!(
+            timer_internal_trigger(TIMER3, TIMER2),
+            Some(TimerTriggerSource::Iti3)
+        );
+        assert_eq!(
+            timer_internal_trigger(TIMER3, TIMER4),
+            Some(TimerTriggerSource::Iti2)
+        );
+        assert_eq!(timer_internal_trigger(TIMER4, TIMER1), None);
+        assert_eq!(timer_internal_trigger(TIMER0, TIMER5), None);
+    }
+
+    // TIMER1 paces the DAC and only counts while TIMER2, running in single
+    // pulse mode, is enabled: a burst of one TIMER2 period.
+    #[test]
+    fn gate_timer1_with_timer2() {
+        sim::reset();
+        sim::preset(TIMER1_SMCFG, 0x0f07);
+
+        assert_eq!(
+            timer_slave_chain_config(TIMER1, TIMER2, TimerSlaveMode::Pause),
+            Some(())
+        );
+        assert_eq!(sim::peek(TIMER1_SMCFG), 0x0f25);
+
+        assert_eq!(timer_master_slave_mode_enable(TIMER1), Some(()));
+        assert_eq!(sim::peek(TIMER1_SMCFG), 0x0fa5);
+        assert_eq!(
+            timer_slave_mode_config(TIMER6, TimerSlaveMode::Restart, TimerTriggerSource::Iti0),
+            None
+        );
+    }
+
+    const TIMER0_DMAINTEN: u32 = 0x4001_2c0c;
+    const TIMER2_DMAINTEN: u32 = 0x4000_040c;
+    const TIMER2_INTF: u32 = 0x4000_0410;
+    const TIMER2_DMACFG: u32 = 0x4000_0448;
+    const TIMER5_CTL0: u32 = 0x4000_1000;
+    const TIMER5_DMAINTEN: u32 = 0x4000_100c;
+    const TIMER5_INTF: u32 = 0x4000_1010;
+
+    #[test]
+    fn interrupts_and_dma_requests_per_kind() {
+        sim::reset();
+        assert_eq!(
+            timer_interrupt_enable(TIMER2, TIMER_INT_UP | TIMER_INT_CH1),
+            Some(())
+        );
+        assert_eq!(timer_dma_enable(TIMER2, TIMER_DMA_UPD), Some(()));
+        assert_eq!(sim::peek(TIMER2_DMAINTEN), 0x105);
+        assert_eq!(timer_interrupt_enable(TIMER2, TIMER_INT_BRK), None);
+        assert_eq!(timer_interrupt_enable(TIMER5, TIMER_INT_CH0), None);
+        assert_eq!(timer_dma_enable(TIMER0, TIMER_DMA_CMTD), Some(()));
+        assert_eq!(sim::peek(TIMER0_DMAINTEN), 0x2000);
+
+        assert_eq!(timer_interrupt_disable(TIMER2, TIMER_INT_UP), Some(()));
+        assert_eq!(sim::peek(TIMER2_DMAINTEN), 0x104);
+    }
+
+    #[test]
+    fn flags_are_cleared_by_writing_zero() {
+        sim::reset();
+        sim::preset(TIMER2_DMAINTEN, TIMER_INT_CH0);
+        sim::preset(
+            TIMER2_INTF,
+            TIMER_FLAG_UP | TIMER_FLAG_CH0 | TIMER_FLAG_CH0O,
+        );
+
+        assert!(timer_flag_get(TIMER2, TIMER_FLAG_UP));
+        assert!(!timer_interrupt_flag_get(TIMER2, TIMER_INT_UP));
+        assert!(timer_interrupt_flag_get(TIMER2, TIMER_INT_CH0));
+
+        sim::take_log();
+        assert_eq!(timer_interrupt_flag_clear(TIMER2, TIMER_INT_CH0), Some(()));
+        assert_eq!(sim::take_writes(), [(TIMER2_INTF, 0x1e5d)]);
+        assert_eq!(timer_flag_clear(TIMER5, TIMER_FLAG_TRG), None);
+    }
+
+    #[test]
+    fn dma_burst_to_prescaler_and_autoreload() {
+        sim::reset();
+        assert_eq!(
+            timer_dma_transfer_config(TIMER2, TIMER_DMACFG_DMATA_PSC, 2),
+            Some(())
+        );
+        assert_eq!(sim::peek(TIMER2_DMACFG), 0x10a);
+        assert_eq!(timer_dma_burst_address(TIMER2), 0x4000_044c);
+
+        assert_eq!(
+            timer_dma_transfer_config(TIMER2, TIMER_DMACFG_DMATA_CCHP, 3),
+            None
+        );
+        assert_eq!(
+            timer_dma_transfer_config(TIMER2, TIMER_DMACFG_DMATA_CTL0, 0),
+            None
+        );
+        assert_eq!(
+            timer_dma_transfer_config(TIMER6, TIMER_DMACFG_DMATA_CAR, 1),
+            None
+        );
+    }
+
+    #[test]
+    fn sample_player_steps_on_update() {
+        static SAMPLES: [u16; 3] = [10, 20, 30];
+        sim::reset();
+        let mut player = SamplePlayer::start(unsafe { Timer5::steal() }, &SAMPLES).unwrap();
+        assert_eq!(sim::peek(TIMER5_DMAINTEN), TIMER_INT_UP);
+        assert_eq!(sim::peek(TIMER5_CTL0), TIMER_CTL0_CEN);
+
+        let mut played = [0; 4];
+        let mut count = 0;
+        for _ in 0..4 {
+            sim::preset(TIMER5_INTF, TIMER_FLAG_UP);
+            player.on_interrupt(|sample| {
+                played[count] = sample;
+                count += 1;
+            });
+            assert_eq!(sim::peek(TIMER5_INTF), 0);
+        }
+        // No update pending, nothing written.
+        player.on_interrupt(|_| count += 1);
+        assert_eq!(played, [10, 20, 30, 10]);
+        assert_eq!(count, 4);
+
+        player.stop();
+        assert_eq!(sim::peek(TIMER5_CTL0), 0);
+        assert_eq!(sim::peek(TIMER5_DMAINTEN), 0);
+    }
+}
@@ -0,0 +1,261 @@
+use crate::csr::*;
+
+/* Trap handling */
+// Every trap enters through `_start_trap`, which saves all registers and the
+// trap CSRs into a `TrapFrame` on the stack. Interrupts are dispatched through
+// the ECLIC vector table; exceptions go to `ExceptionHandler`, which the
+// application can override by name:
+//
+//     #[no_mangle]
+//     extern "C" fn ExceptionHandler(frame: &mut TrapFrame) { ... }
+//
+// If it returns, execution resumes at `frame.mepc` with the registers from
+// `frame`, so a handler that wants to skip the faulting instruction has to
+// advance `mepc`. The default handler never returns.
+
+// Registers in x1..x31 order, then the trap CSRs.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct TrapFrame {
+    pub ra: u32,
+    pub sp: u32,
+    pub gp: u32,
+    pub tp: u32,
+    pub t0: u32,
+    pub t1: u32,
+    pub t2: u32,
+    pub s0: u32,
+    pub s1: u32,
+    pub a0: u32,
+    pub a1: u32,
+    pub a2: u32,
+    pub a3: u32,
+    pub a4: u32,
+    pub a5: u32,
+    pub a6: u32,
+    pub a7: u32,
+    pub s2: u32,
+    pub s3: u32,
+    pub s4: u32,
+    pub s5: u32,
+    pub s6: u32,
+    pub s7: u32,
+    pub s8: u32,
+    pub s9: u32,
+    pub s10: u32,
+    pub s11: u32,
+    pub t3: u32,
+    pub t4: u32,
+    pub t5: u32,
+    pub t6: u32,
+    pub mcause: u32,
+    pub mepc: u32,
+    pub mtval: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Exception {
+    InstructionMisaligned,
+    InstructionFault,
+    IllegalInstruction,
+    Breakpoint,
+    LoadMisaligned,
+    LoadFault,
+    StoreMisaligned,
+    StoreFault,
+    UserEnvCall,
+    MachineEnvCall,
+    Unknown(u16),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TrapCause {
+    // ECLIC interrupt id, see `eclic::Interrupt`.
+    Interrupt(u16),
+    Exception(Exception),
+}
+
+impl TrapCause {
+    // On this core mcause also holds the previous interrupt level and
+    // privilege mode; only the interrupt bit and the code matter here.
+    pub const fn from_mcause(mcause: u32) -> TrapCause {
+        let code = (mcause & MCAUSE_EXCCODE) as u16;
+        if mcause & MCAUSE_INTERRUPT != 0 {
+            return TrapCause::Interrupt(code);
+        }
+        TrapCause::Exception(match code {
+            0 => Exception::InstructionMisaligned,
+            1 => Exception::InstructionFault,
+            2 => Exception::IllegalInstruction,
+            3 => Exception::Breakpoint,
+            4 => Exception::LoadMisaligned,
+            5 => Exception::LoadFault,
+            6 => Exception::StoreMisaligned,
+            7 => Exception::StoreFault,
+            8 => Exception::UserEnvCall,
+            11 => Exception::MachineEnvCall,
+            code => Exception::Unknown(code),
+        })
+    }
+}
+
+impl TrapFrame {
+    pub const fn cause(&self) -> TrapCause {
+        TrapCause::from_mcause(self.mcause)
+    }
+}
+
+// The frame takes 34 words, rounded up to 36 to keep sp 16-byte aligned.
+#[cfg(target_arch = "riscv32")]
+core::arch::global_asm!(
+    r#"
+.section .text._start_trap
+.global _start_trap
+.align 6
+_start_trap:
+  addi sp, sp, -36*4
+  sw ra, 0*4(sp)
+  sw gp, 2*4(sp)
+  sw tp, 3*4(sp)
+  sw t0, 4*4(sp)
+  sw t1, 5*4(sp)
+  sw t2, 6*4(sp)
+  sw s0, 7*4(sp)
+  sw s1, 8*4(sp)
+  sw a0, 9*4(sp)
+  sw a1, 10*4(sp)
+  sw a2, 11*4(sp)
+  sw a3, 12*4(sp)
+  sw a4, 13*4(sp)
+  sw a5, 14*4(sp)
+  sw a6, 15*4(sp)
+  sw a7, 16*4(sp)
+  sw s2, 17*4(sp)
+  sw s3, 18*4(sp)
+  sw s4, 19*4(sp)
+  sw s5, 20*4(sp)
+  sw s6, 21*4(sp)
+  sw s7, 22*4(sp)
+  sw s8, 23*4(sp)
+  sw s9, 24*4(sp)
+  sw s10, 25*4(sp)
+  sw s11, 26*4(sp)
+  sw t3, 27*4(sp)
+  sw t4, 28*4(sp)
+  sw t5, 29*4(sp)
+  sw t6, 30*4(sp)
+  addi t0, sp, 36*4
+  sw t0, 1*4(sp)
+  csrr t0, mcause
+  sw t0, 31*4(sp)
+  csrr t0, mepc
+  sw t0, 32*4(sp)
+  csrr t0, mtval
+  sw t0, 33*4(sp)
+
+  mv a0, sp
+  call _start_trap_rust
+
+  lw t0, 32*4(sp)
+  csrw mepc, t0
+  lw ra, 0*4(sp)
+  lw gp, 2*4(sp)
+  lw tp, 3*4(sp)
+  lw t0, 4*4(sp)
+  lw t1, 5*4(sp)
+  lw t2, 6*4(sp)
+  lw s0, 7*4(sp)
+  lw s1, 8*4(sp)
+  lw a0, 9*4(sp)
+  lw a1, 10*4(sp)
+  lw a2, 11*4(sp)
+  lw a3, 12*4(sp)
+  lw a4, 13*4(sp)
+  lw a5, 14*4(sp)
+  lw a6, 15*4(sp)
+  lw a7, 16*4(sp)
+  lw s2, 17*4(sp)
+  lw s3, 18*4(sp)
+  lw s4, 19*4(sp)
+  lw s5, 20*4(sp)
+  lw s6, 21*4(sp)
+  lw s7, 22*4(sp)
+  lw s8, 23*4(sp)
+  lw s9, 24*4(sp)
+  lw s10, 25*4(sp)
+  lw s11, 26*4(sp)
+  lw t3, 27*4(sp)
+  lw t4, 28*4(sp)
+  lw t5, 29*4(sp)
+  lw t6, 30*4(sp)
+  addi sp, sp, 36*4
+  mret
+"#
+);
+
+#[cfg(target_arch = "riscv32")]
+#[no_mangle]
+extern "C" fn _start_trap_rust(frame: &mut TrapFrame) {
+    extern "C" {
+        fn ExceptionHandler(frame: &mut TrapFrame);
+    }
+
+    match frame.cause() {
+        TrapCause::Interrupt(source) => crate::eclic::eclic_dispatch(source),
+        TrapCause::Exception(_) => unsafe { ExceptionHandler(frame) },
+    }
+}
+
+// Parks the core with the frame in a0, where a debugger can inspect it.
+#[cfg(target_arch = "riscv32")]
+#[no_mangle]
+extern "C" fn DefaultExceptionHandler_(frame: &mut TrapFrame) -> ! {
+    let _ = core::hint::black_box(frame);
+    loop {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_recorded_causes() {
+        // Illegal instruction from machine mode, interrupts were enabled.
+        assert_eq!(
+            TrapCause::from_mcause(0x3800_0002),
+            TrapCause::Exception(Exception::IllegalInstruction)
+        );
+        // TIMER5 interrupt taken from level 0.
+        assert_eq!(
+            TrapCause::from_mcause(0xb800_0049),
+            TrapCause::Interrupt(crate::eclic::Interrupt::Timer5 as u16)
+        );
+        // Store to an unmapped address.
+        assert_eq!(
+            TrapCause::from_mcause(0x3800_0007),
+            TrapCause::Exception(Exception::StoreFault)
+        );
+        assert_eq!(
+            TrapCause::from_mcause(0x3000_000b),
+            TrapCause::Exception(Exception::MachineEnvCall)
+        );
+        assert_eq!(
+            TrapCause::from_mcause(0x0000_000e),
+            TrapCause::Exception(Exception::Unknown(14))
+        );
+    }
+
+    #[test]
+    fn frame_layout_matches_trap_entry() {
+        let frame = TrapFrame::default();
+        let base = &frame as *const TrapFrame as usize;
+
+        assert_eq!(&frame.sp as *const u32 as usize - base, 4);
+        assert_eq!(&frame.a0 as *const u32 as usize - base, 9 * 4);
+        assert_eq!(&frame.t6 as *const u32 as usize - base, 30 * 4);
+        assert_eq!(&frame.mcause as *const u32 as usize - base, 31 * 4);
+        assert_eq!(&frame.mepc as *const u32 as usize - base, 32 * 4);
+        assert_eq!(&frame.mtval as *const u32 as usize - base, 33 * 4);
+        assert!(core::mem::size_of::<TrapFrame>() <= 36 * 4);
+    }
+}
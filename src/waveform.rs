@@ -0,0 +1,300 @@
+/* Waveform tables */
+// Builds one period of a waveform into a caller-provided buffer, ready to be
+// played through DMA into a DAC holding register. Everything is integer
+// fixed-point and `const`, so tables can be built at compile time:
+//
+//     static SINE: [u16; 64] = {
+//         let mut table = [0; 64];
+//         Waveform::new(Shape::Sine, Resolution::Bits12).fill(&mut table);
+//         table
+//     };
+//
+// Internally, phase and level are fractions of a full period / full scale in
+// 16-bit fixed-point (0x1_0000 is one period, 0xffff is full scale).
+
+const PHASE_ONE: u32 = 0x1_0000;
+const LEVEL_MAX: u32 = 0xffff;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Resolution {
+    Bits8,
+    Bits12,
+}
+
+impl Resolution {
+    pub const fn max(self) -> u16 {
+        match self {
+            Resolution::Bits8 => 0xff,
+            Resolution::Bits12 => 0xfff,
+        }
+    }
+}
+
+// A corner of a piecewise-linear waveform. `phase` is the position within the
+// period and `level` the value at that position, both as fractions of 0x1_0000.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Point {
+    pub phase: u16,
+    pub level: u16,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Shape<'a> {
+    Sine,
+    // High for the first `duty_percent` percent of the period.
+    Square { duty_percent: u8 },
+    // Rising ramp.
+    Sawtooth,
+    Triangle,
+    // Linear interpolation between points sorted by phase, wrapping around from
+    // the last point to the first.
+    PiecewiseLinear(&'a [Point]),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Waveform<'a> {
+    pub shape: Shape<'a>,
+    pub resolution: Resolution,
+    pub amplitude: u16, // peak-to-peak, in DAC codes
+    pub offset: u16,    // lowest value, in DAC codes
+}
+
+impl<'a> Waveform<'a> {
+    // Full-scale waveform at the given resolution.
+    pub const fn new(shape: Shape<'a>, resolution: Resolution) -> Waveform<'a> {
+        Waveform {
+            shape,
+            resolution,
+            amplitude: resolution.max(),
+            offset: 0,
+        }
+    }
+
+    pub const fn amplitude(mut self, amplitude: u16) -> Waveform<'a> {
+        self.amplitude = amplitude;
+        self
+    }
+
+    pub const fn offset(mut self, offset: u16) -> Waveform<'a> {
+        self.offset = offset;
+        self
+    }
+
+    // Sample `index` of a table holding one period in `len` samples. Values
+    // beyond the resolution are clipped. An empty table has no period, so the
+    // result for `len == 0` is just the offset.
+    pub const fn sample(&self, index: usize, len: usize) -> u16 {
+        let value = if len == 0 {
+            self.offset as u32
+        } else {
+            let phase = ((index as u64 * PHASE_ONE as u64) / len as u64) as u32;
+            let level = shape_level(self.shape, phase);
+            self.offset as u32 + (self.amplitude as u32 * level + LEVEL_MAX / 2) / LEVEL_MAX
+        };
+        let max = self.resolution.max() as u32;
+        if value > max {
+            max as u16
+        } else {
+            value as u16
+        }
+    }
+
+    // Fills `buffer` with exactly one period.
+    pub const fn fill(&self, buffer: &mut [u16]) {
+        let mut i = 0;
+        while i < buffer.len() {
+            buffer[i] = self.sample(i, buffer.len());
+            i += 1;
+        }
+    }
+}
+
+// Level (0..=LEVEL_MAX) of `shape` at `phase` (0..PHASE_ONE).
+const fn shape_level(shape: Shape, phase: u32) -> u32 {
+    match shape {
+        Shape::Sine => ((sine(phase) + PHASE_ONE as i32) / 2) as u32,
+        Shape::Square { duty_percent } => {
+            if phase < PHASE_ONE * duty_percent as u32 / 100 {
+                LEVEL_MAX
+            } else {
+                0
+            }
+        }
+        Shape::Sawtooth => phase * LEVEL_MAX / (PHASE_ONE - 1),
+        Shape::Triangle => {
+            let rising = if phase < PHASE_ONE / 2 {
+                phase
+            } else {
+                PHASE_ONE - phase
+            };
+            let level = rising * 2;
+            if level > LEVEL_MAX {
+                LEVEL_MAX
+            } else {
+                level
+            }
+        }
+        Shape::PiecewiseLinear(points) => piecewise_linear(points, phase),
+    }
+}
+
+// sin(2π·phase) scaled to ±0xffff, from its Taylor series up to x^9 over a
+// quarter period, evaluated in Q30 (error far below one LSB at 12 bits).
+const fn sine(phase: u32) -> i32 {
+    const ONE: i64 = 1 << 30;
+    const HALF_PI: i64 = 1_686_629_713; // π/2 in Q30
+
+    let quarter = PHASE_ONE / 4;
+    let (x, negative) = match phase / quarter {
+        0 => (phase, false),
+        1 => (PHASE_ONE / 2 - phase, false),
+        2 => (phase - PHASE_ONE / 2, true),
+        _ => (PHASE_ONE - phase, true),
+    };
+    let x = HALF_PI * x as i64 / quarter as i64;
+    let x2 = (x * x) >> 30;
+    // x·(1 - x²/6·(1 - x²/20·(1 - x²/42·(1 - x²/72))))
+    let mut t = ONE - x2 / 72;
+    t = ONE - ((x2 * t) >> 30) / 42;
+    t = ONE - ((x2 * t) >> 30) / 20;
+    t = ONE - ((x2 * t) >> 30) / 6;
+    let value = ((((x * t) >> 30) * LEVEL_MAX as i64) + ONE / 2) >> 30;
+    if negative {
+        -(value as i32)
+    } else {
+        value as i32
+    }
+}
+
+const fn piecewise_linear(points: &[Point], phase: u32) -> u32 {
+    let n = points.len();
+    if n == 0 {
+        return 0;
+    }
+
+    // Find the last point at or before `phase`; before the first point we are
+    // still on the segment wrapping around from the last one.
+    let mut i = 0;
+    while i + 1 < n && points[i + 1].phase as u32 <= phase {
+        i += 1;
+    }
+    let (start, end) = if (points[0].phase as u32) > phase {
+        (n - 1, 0)
+    } else {
+        (i, (i + 1) % n)
+    };
+
+    let start_phase = points[start].phase as u32;
+    let mut end_phase = points[end].phase as u32;
+    if end <= start {
+        end_phase += PHASE_ONE;
+    }
+    let mut position = phase;
+    if position < start_phase {
+        position += PHASE_ONE;
+    }
+
+    let from = points[start].level as i64;
+    let to = points[end].level as i64;
+    let width = (end_phase - start_phase) as i64;
+    if width == 0 {
+        return from as u32;
+    }
+    (from + (to - from) * (position - start_phase) as i64 / width) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sine_matches_float_reference() {
+        let mut table = [0; 256];
+        Waveform::new(Shape::Sine, Resolution::Bits12).fill(&mut table);
+
+        for (i, &value) in table.iter().enumerate() {
+            let angle = 2.0 * core::f64::consts::PI * i as f64 / table.len() as f64;
+            let expected = (1.0 + angle.sin()) / 2.0 * 4095.0;
+            assert!(
+                (value as f64 - expected).abs() <= 1.0,
+                "sample {}: {} vs {}",
+                i,
+                value,
+                expected
+            );
+        }
+        assert_eq!(table[0], 2048);
+        assert_eq!(table[64], 4095);
+        assert_eq!(table[192], 0);
+    }
+
+    #[test]
+    fn square_duty_cycle() {
+        let mut table = [0; 10];
+        Waveform::new(Shape::Square { duty_percent: 30 }, Resolution::Bits8).fill(&mut table);
+
+        assert_eq!(table, [255, 255, 255, 0, 0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn sawtooth_and_triangle() {
+        let mut saw = [0; 4];
+        Waveform::new(Shape::Sawtooth, Resolution::Bits8).fill(&mut saw);
+        assert_eq!(saw, [0, 64, 128, 191]);
+
+        let mut triangle = [0; 4];
+        Waveform::new(Shape::Triangle, Resolution::Bits8).fill(&mut triangle);
+        assert_eq!(triangle, [0, 128, 255, 128]);
+    }
+
+    #[test]
+    fn amplitude_and_offset_scaling() {
+        let mut table = [0; 4];
+        Waveform::new(Shape::Triangle, Resolution::Bits12)
+            .amplitude(1000)
+            .offset(100)
+            .fill(&mut table);
+        assert_eq!(table, [100, 600, 1100, 600]);
+
+        // Clipped at the resolution's maximum.
+        Waveform::new(Shape::Triangle, Resolution::Bits8)
+            .offset(200)
+            .fill(&mut table);
+        assert_eq!(table, [200, 255, 255, 255]);
+    }
+
+    #[test]
+    fn piecewise_linear_interpolates_and_wraps() {
+        const POINTS: [Point; 2] = [
+            Point {
+                phase: 0x4000,
+                level: 0xffff,
+            },
+            Point {
+                phase: 0xc000,
+                level: 0,
+            },
+        ];
+        let mut table = [0; 8];
+        Waveform::new(Shape::PiecewiseLinear(&POINTS), Resolution::Bits8).fill(&mut table);
+
+        assert_eq!(table, [127, 191, 255, 191, 128, 64, 0, 64]);
+    }
+
+    #[test]
+    fn empty_table_gives_offset() {
+        let waveform = Waveform::new(Shape::Sine, Resolution::Bits8).offset(0x20);
+        assert_eq!(waveform.sample(3, 0), 0x20);
+        assert_eq!(waveform.offset(0x1000).sample(0, 0), 0xff);
+    }
+
+    #[test]
+    fn usable_in_const_context() {
+        const TABLE: [u16; 4] = {
+            let mut table = [0; 4];
+            Waveform::new(Shape::Sine, Resolution::Bits8).fill(&mut table);
+            table
+        };
+        assert_eq!(TABLE, [128, 255, 128, 0]);
+    }
+}